@@ -1,7 +1,10 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 use std::fmt::{Display, Formatter, Error as FmtError};
 
+use crate::Result;
+
 #[derive(Debug, Clone)]
 pub struct ClientToken {
     pub client_id: String,
@@ -50,10 +53,37 @@ pub struct ExchangeCode {
     pub code: String
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct AuthResponse {
     pub access_token: String,
-    pub refresh_token: String,
+    pub refresh_token: Option<String>,
     pub expires_at: String,
-    pub refresh_expires_at: String
+    pub refresh_expires_at: Option<String>,
+    /// Missing for grants that don't authenticate an account, such as `client_credentials`.
+    pub account_id: Option<String>,
+    #[serde(rename = "displayName")]
+    pub display_name: Option<String>
+}
+
+impl AuthResponse {
+    /// # Errors
+    /// 
+    /// Will return `Err` if `expires_at` is not a valid RFC 3339 timestamp
+    pub fn expires_at(&self) -> Result<DateTime<Utc>> {
+        parse_timestamp(&self.expires_at)
+    }
+
+    /// Returns `None` for grants without a refresh token, such as `client_credentials`.
+    ///
+    /// # Errors
+    /// 
+    /// Will return `Err` if `refresh_expires_at` is not a valid RFC 3339 timestamp
+    pub fn refresh_expires_at(&self) -> Result<Option<DateTime<Utc>>> {
+        self.refresh_expires_at.as_deref().map(parse_timestamp).transpose()
+    }
+}
+
+// Epic returns timestamps like `2023-01-01T12:34:56.789Z`
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(value)?.with_timezone(&Utc))
 }
\ No newline at end of file
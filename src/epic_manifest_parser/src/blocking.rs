@@ -0,0 +1,133 @@
+use std::future::Future;
+use std::path::Path;
+
+use tokio::runtime::{Builder, Runtime};
+
+use crate::auth::{AuthResponse, ClientToken, Device, ExchangeCode};
+use crate::chunk::FileManifest;
+use crate::manifest::{InstallOptions, Manifest, ManifestInfo};
+use crate::{ClientResult, DownloadError, EpicGamesClient, Result};
+
+/// Runs the network methods of an `EpicGamesClient`, and the downloads of the manifests it fetched,
+/// to completion on a runtime of its own, for programs that don't use async.
+/// 
+/// Must not be used from within an async runtime, blocking on it panics.
+pub struct BlockingClient {
+    client: EpicGamesClient,
+    runtime: Runtime
+}
+
+impl EpicGamesClient {
+
+    /// Turns the client into a `BlockingClient`, see `BlockingClient::into_inner` to get it back.
+    /// 
+    /// # Errors
+    /// 
+    /// Will return `Err` if the runtime can't be created
+    pub fn blocking(self) -> ClientResult<BlockingClient> {
+        let runtime = Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        Ok(BlockingClient {
+            client: self,
+            runtime
+        })
+    }
+
+}
+
+impl BlockingClient {
+
+    /// The wrapped client, to change its settings or read the authentication.
+    pub fn client(&self) -> &EpicGamesClient {
+        &self.client
+    }
+
+    pub fn client_mut(&mut self) -> &mut EpicGamesClient {
+        &mut self.client
+    }
+
+    pub fn into_inner(self) -> EpicGamesClient {
+        self.client
+    }
+
+    /// Runs `future` on the runtime of the client, for the async methods that have no blocking version.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    /// See `EpicGamesClient::get_manifest_info_authenticated`.
+    pub fn get_manifest_info_authenticated(&mut self, url: &str) -> ClientResult<ManifestInfo> {
+        self.runtime.block_on(self.client.get_manifest_info_authenticated(url))
+    }
+
+    /// See `EpicGamesClient::get_manifest_info`.
+    pub fn get_manifest_info(&self, url: &str) -> ClientResult<ManifestInfo> {
+        self.runtime.block_on(self.client.get_manifest_info(url))
+    }
+
+    /// See `EpicGamesClient::authenticate_with_device`.
+    pub fn authenticate_with_device(&mut self, device: &Device, client_token: &ClientToken) -> ClientResult<&AuthResponse> {
+        self.runtime.block_on(self.client.authenticate_with_device(device, client_token))
+    }
+
+    /// See `EpicGamesClient::authenticate_with_exchange`.
+    pub fn authenticate_with_exchange(&mut self, client_token: &ClientToken) -> ClientResult<&AuthResponse> {
+        self.runtime.block_on(self.client.authenticate_with_exchange(client_token))
+    }
+
+    /// See `EpicGamesClient::authenticate_with_authorization_code`.
+    pub fn authenticate_with_authorization_code(&mut self, client_token: &ClientToken, code: &str) -> ClientResult<&AuthResponse> {
+        self.runtime.block_on(self.client.authenticate_with_authorization_code(client_token, code))
+    }
+
+    /// See `EpicGamesClient::authenticate_with_client_credentials`.
+    pub fn authenticate_with_client_credentials(&mut self, client_token: &ClientToken) -> ClientResult<&AuthResponse> {
+        self.runtime.block_on(self.client.authenticate_with_client_credentials(client_token))
+    }
+
+    /// See `EpicGamesClient::refresh_authentication`.
+    pub fn refresh_authentication(&mut self, client_token: &ClientToken) -> ClientResult<&AuthResponse> {
+        self.runtime.block_on(self.client.refresh_authentication(client_token))
+    }
+
+    /// See `EpicGamesClient::get_exchange_code`.
+    pub fn get_exchange_code(&mut self) -> ClientResult<ExchangeCode> {
+        self.runtime.block_on(self.client.get_exchange_code())
+    }
+
+    /// See `EpicGamesClient::download_manifest_async`.
+    pub fn download_manifest(&self, manifest: &ManifestInfo, cache_dir: Option<&str>) -> ClientResult<Vec<u8>> {
+        self.runtime.block_on(self.client.download_manifest_async(manifest, cache_dir))
+    }
+
+    /// See `EpicGamesClient::download_manifest_with_progress`.
+    pub fn download_manifest_with_progress<F>(&self, manifest: &ManifestInfo, cache_dir: Option<&str>, progress: F) -> ClientResult<Vec<u8>>
+    where
+        F: Fn(u64, Option<u64>)
+    {
+        self.runtime.block_on(self.client.download_manifest_with_progress(manifest, cache_dir, progress))
+    }
+
+    /// See `EpicGamesClient::logout`.
+    pub fn logout(&mut self) -> ClientResult<()> {
+        self.runtime.block_on(self.client.logout())
+    }
+
+    /// See `FileManifest::save`.
+    pub fn save_file(&self, file: &FileManifest) -> std::result::Result<Vec<u8>, DownloadError> {
+        self.runtime.block_on(file.save())
+    }
+
+    /// See `Manifest::download_file`.
+    pub fn download_file(&self, manifest: &Manifest, file_name: &str, output: &Path) -> Result<()> {
+        self.runtime.block_on(manifest.download_file(file_name, output))
+    }
+
+    /// See `Manifest::install`.
+    pub fn install(&self, manifest: &Manifest, output_dir: &Path, options: InstallOptions) -> Result<()> {
+        self.runtime.block_on(manifest.install(output_dir, options))
+    }
+
+}
\ No newline at end of file
@@ -0,0 +1,91 @@
+use std::fmt::Debug;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+
+use crate::chunk::FileChunk;
+
+/// Storage for decompressed chunks, set with `ManifestOptions::set_chunk_cache`. Chunks are keyed by
+/// `FileChunk::guid`, the other fields of the chunk can be used to lay out the storage.
+/// 
+/// Implementations use `#[async_trait::async_trait]` and shouldn't block, the methods are awaited
+/// by the chunk downloads.
+#[async_trait]
+pub trait ChunkCache: Debug + Send + Sync {
+
+    /// Returns `None` if the chunk isn't cached.
+    /// 
+    /// # Errors
+    /// 
+    /// Will return `Err` if the cached chunk exists but can't be read
+    async fn get(&self, chunk: &FileChunk) -> std::io::Result<Option<Vec<u8>>>;
+
+    /// # Errors
+    /// 
+    /// Will return `Err` if the chunk can't be stored
+    async fn put(&self, chunk: &FileChunk, data: &[u8]) -> std::io::Result<()>;
+
+    /// Checks whether the chunk is cached without reading it, if the storage allows that.
+    async fn contains(&self, chunk: &FileChunk) -> bool {
+        matches!(self.get(chunk).await, Ok(Some(_)))
+    }
+
+}
+
+/// The default cache, storing every chunk as `{hash}_{guid}.chunk` in a directory.
+#[derive(Debug)]
+pub struct FileSystemCache {
+    pub directory: PathBuf,
+    pub shard: bool
+}
+
+impl FileSystemCache {
+
+    /// With `shard` set, chunks are stored in subdirectories named after the first two characters
+    /// of their hash.
+    pub fn new(directory: &Path, shard: bool) -> Self {
+        Self {
+            directory: directory.to_owned(),
+            shard
+        }
+    }
+
+    /// Path of the chunk in the cache directory.
+    pub fn path(&self, chunk: &FileChunk) -> PathBuf {
+        // chunk file names start with the chunk hash
+        match chunk.file_name.get(..2) {
+            Some(shard) if self.shard => self.directory.join(shard).join(&chunk.file_name),
+            _ => self.directory.join(&chunk.file_name)
+        }
+    }
+
+}
+
+#[async_trait]
+impl ChunkCache for FileSystemCache {
+
+    async fn get(&self, chunk: &FileChunk) -> std::io::Result<Option<Vec<u8>>> {
+        match tokio::fs::read(self.path(chunk)).await {
+            Ok(data) => Ok(Some(data)),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error)
+        }
+    }
+
+    async fn put(&self, chunk: &FileChunk, data: &[u8]) -> std::io::Result<()> {
+        let path = self.path(chunk);
+        if self.shard {
+            if let Some(shard_dir) = path.parent() {
+                tokio::fs::create_dir_all(shard_dir).await?;
+            }
+        }
+
+        tokio::fs::write(path, data).await
+    }
+
+    async fn contains(&self, chunk: &FileChunk) -> bool {
+        tokio::fs::metadata(self.path(chunk)).await.is_ok()
+    }
+
+}
\ No newline at end of file
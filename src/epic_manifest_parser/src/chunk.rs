@@ -1,14 +1,43 @@
 use miniz_oxide::inflate::decompress_to_vec_zlib;
-use byteorder::ReadBytesExt;
-use thoo_readext::ReadExt;
+use byteorder::{LittleEndian, ReadBytesExt};
+use serde::Serialize;
+use bytes::Bytes;
+use futures::stream::{self, Stream, StreamExt};
 
-use std::io::{Cursor, Seek, SeekFrom};
-use std::collections::HashMap;
-use std::path::PathBuf;
-use std::sync::{Arc, mpsc, Mutex};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
-use crate::{manifest::FGuid, http::HttpService}; // in an other file
-use crate::Result;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite};
+use tokio::sync::{mpsc, OnceCell, Semaphore};
+use tokio::task::JoinHandle;
+use tokio_util::io::StreamReader;
+use tokio_util::sync::CancellationToken;
+
+use crate::{manifest::{FGuid, ChunkSha, ManifestOptions}, cache::{ChunkCache, FileSystemCache}, hash, http::{self, HttpError, HttpService}, memory_cache::ChunkMemoryCache, throttle::RateLimiter}; // in an other file
+use crate::{Result, ParserError, CancelledError, DownloadError};
+
+// Delay before the first retry, doubled on every following attempt up to the maximum
+const RETRY_BASE_DELAY_MS: u64 = 500;
+const RETRY_MAX_DELAY_MS: u64 = 30_000;
+
+/// CDN location of a chunk: `{base}{data_group:02}/{hash}_{guid}.chunk`.
+pub fn chunk_uri(base: &str, data_group: u8, hash: &str, guid: &FGuid) -> String {
+    format!("{}{:02}/{}_{}.chunk", base, data_group, hash, guid)
+}
+
+/// CDN location of a file in a file data manifest: `{base}{sha}_{guid}.file`.
+pub fn file_data_uri(base: &str, sha: &str, guid: &FGuid) -> String {
+    format!("{}{}_{}.file", base, sha, guid)
+}
 
 #[derive(Debug)]
 pub struct FileChunk {
@@ -18,7 +47,9 @@ pub struct FileChunk {
     pub sha: String,
     pub data_group: u8,
     pub file_name: String,
-    pub uri: String
+    pub uri: String,
+    /// The chunk under the other base uris, tried in order when `uri` fails.
+    pub mirror_uris: Vec<String>
 }
 
 impl FileChunk {
@@ -30,13 +61,126 @@ impl FileChunk {
             hash: hash.to_owned(),
             sha: sha.to_owned(),
             data_group,
-            file_name: file_name.clone(),
-            uri: format!("{}{:02}/{}", base_url, data_group, file_name)
+            file_name,
+            uri: chunk_uri(base_url, data_group, hash, &guid),
+            mirror_uris: Vec::new()
         }
     }
+
+    /// The data of a whole file in a file data manifest, which only has a SHA1 and no rolling hash.
+    pub fn file_data(guid: FGuid, size: u64, sha: &str, base_url: &str) -> Self {
+        Self {
+            guid,
+            size,
+            hash: String::new(),
+            sha: sha.to_owned(),
+            data_group: 0,
+            file_name: format!("{}_{}.file", sha, guid),
+            uri: file_data_uri(base_url, sha, &guid),
+            mirror_uris: Vec::new()
+        }
+    }
+
+    /// Adds the location of the chunk under `base_url` to `mirror_uris`.
+    pub fn add_mirror(&mut self, base_url: &str) {
+        let uri = if self.hash.is_empty() {
+            file_data_uri(base_url, &self.sha, &self.guid)
+        } else {
+            chunk_uri(base_url, self.data_group, &self.hash, &self.guid)
+        };
+
+        self.mirror_uris.push(uri);
+    }
 }
 
+pub const CHUNK_HEADER_MAGIC: u32 = 0xB1FE3AA2;
+/// Size of the newest header version, enough to read the header of any chunk.
+pub const CHUNK_HEADER_MAX_SIZE: u64 = 66;
+
+const CHUNK_STORED_COMPRESSED: u8 = 0x01;
+const CHUNK_STORED_ENCRYPTED: u8 = 0x02;
+
+/// How the data following a chunk header is stored, see [`ChunkHeader::storage`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkStorage {
+    Raw,
+    Zlib
+}
+
+/// Header at the start of every chunk file served by the CDN.
 #[derive(Debug)]
+pub struct ChunkHeader {
+    pub version: u32,
+    pub header_size: u32,
+    pub data_size_compressed: u32,
+    pub guid: FGuid,
+    pub hash: u64,
+    pub stored_flags: u8,
+    pub sha: Option<ChunkSha>,
+    pub data_size_uncompressed: Option<u32>
+}
+
+impl ChunkHeader {
+    /// # Errors
+    /// 
+    /// Will return `Err` if the reader doesn't start with a chunk header
+    pub fn new<R>(reader: &mut R) -> Result<Self>
+    where
+        R: Read
+    {
+        let magic = reader.read_u32::<LittleEndian>()?;
+        if magic != CHUNK_HEADER_MAGIC {
+            return Err(Box::new(ParserError::new(&format!("invalid chunk header magic {:#010X}", magic))));
+        }
+
+        let version = reader.read_u32::<LittleEndian>()?;
+        let header_size = reader.read_u32::<LittleEndian>()?;
+        let data_size_compressed = reader.read_u32::<LittleEndian>()?;
+        let guid = FGuid::new(reader)?;
+        let hash = reader.read_u64::<LittleEndian>()?;
+        let stored_flags = reader.read_u8()?;
+
+        let mut sha = None;
+        if version >= 2 {
+            sha = Some(ChunkSha::new(reader)?);
+            reader.read_u8()?; // hash type
+        }
+
+        let mut data_size_uncompressed = None;
+        if version >= 3 {
+            data_size_uncompressed = Some(reader.read_u32::<LittleEndian>()?);
+        }
+
+        Ok(Self {
+            version,
+            header_size,
+            data_size_compressed,
+            guid,
+            hash,
+            stored_flags,
+            sha,
+            data_size_uncompressed
+        })
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.stored_flags & CHUNK_STORED_COMPRESSED != 0
+    }
+
+    /// # Errors
+    /// 
+    /// Will return `Err` if the storage flags are encrypted or unknown, rather than guessing how to read the data
+    pub fn storage(&self) -> Result<ChunkStorage> {
+        match self.stored_flags {
+            0 => Ok(ChunkStorage::Raw),
+            CHUNK_STORED_COMPRESSED => Ok(ChunkStorage::Zlib),
+            flags if flags & CHUNK_STORED_ENCRYPTED != 0 => Err(Box::new(ParserError::new(&format!("chunk {} is encrypted, which is not supported", self.guid)))),
+            flags => Err(Box::new(ParserError::new(&format!("chunk {} uses unsupported storage flags {:#04X}", self.guid, flags))))
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub struct FileChunkPart {
     pub guid: FGuid, 
     pub offset: i32,
@@ -44,12 +188,15 @@ pub struct FileChunkPart {
 }
 
 impl FileChunkPart {
-    pub fn new(reader: &mut Cursor<Vec<u8>>) -> Result<Self> {
-        reader.seek(SeekFrom::Current(4)).unwrap();
+    pub fn new<R>(reader: &mut R) -> Result<Self>
+    where
+        R: Read + Seek
+    {
+        reader.seek(SeekFrom::Current(4))?;
 
         let guid = FGuid::new(reader)?;
-        let offset = reader.read_i32_le()?;
-        let size = reader.read_i32_le()?;
+        let offset = reader.read_i32::<LittleEndian>()?;
+        let size = reader.read_i32::<LittleEndian>()?;
 
         Ok(Self {
             guid,
@@ -59,24 +206,377 @@ impl FileChunkPart {
     }
 }
 
+/// Where the chunks loaded through a `ManifestContext` came from, see `ManifestContext::download_stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct DownloadStats {
+    /// Chunks loaded from the memory or disk cache.
+    pub cache_hits: u64,
+    /// Decompressed bytes of the chunks loaded from the cache.
+    pub cached_bytes: u64,
+    /// Chunks downloaded, including chunks of which only a part was fetched.
+    pub downloaded_chunks: u64,
+    /// Bytes received, before decompression.
+    pub downloaded_bytes: u64,
+    /// Time spent downloading, summed over concurrent downloads so it can exceed the elapsed time.
+    pub download_time: Duration
+}
+
+#[derive(Debug, Default)]
+struct DownloadCounters {
+    cache_hits: AtomicU64,
+    cached_bytes: AtomicU64,
+    downloaded_chunks: AtomicU64,
+    downloaded_bytes: AtomicU64,
+    download_time_ns: AtomicU64
+}
+
+impl DownloadCounters {
+    fn cache_hit(&self, size: usize) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+        self.cached_bytes.fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    fn downloaded(&self, size: usize, time: Duration) {
+        self.downloaded_bytes.fetch_add(size as u64, Ordering::Relaxed);
+        self.download_time_ns.fetch_add(u64::try_from(time.as_nanos()).unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> DownloadStats {
+        DownloadStats {
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            cached_bytes: self.cached_bytes.load(Ordering::Relaxed),
+            downloaded_chunks: self.downloaded_chunks.load(Ordering::Relaxed),
+            downloaded_bytes: self.downloaded_bytes.load(Ordering::Relaxed),
+            download_time: Duration::from_nanos(self.download_time_ns.load(Ordering::Relaxed))
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ManifestContext {
     pub chunks: Arc<HashMap<FGuid, FileChunk>>,
     pub http: Arc<HttpService>,
-    pub cache_dir: Option<String>
+    pub cache: Option<Arc<dyn ChunkCache>>,
+    pub verify_chunks: bool,
+    pub offline: bool,
+    pub max_retries: u32,
+    pub max_concurrent_downloads: usize,
+    pub download_semaphore: Semaphore,
+    pub rate_limiter: Option<RateLimiter>,
+    pub in_flight: Mutex<HashMap<FGuid, Arc<OnceCell<Bytes>>>>,
+    pub memory_cache: Option<ChunkMemoryCache>,
+    stats: DownloadCounters,
+    failed_hosts: Mutex<HashSet<String>>
 }
 
 impl ManifestContext {
-    pub fn new(chunks: Arc<HashMap<FGuid, FileChunk>>, http: Arc<HttpService>, cache_dir: Option<String>) -> Self {
+    pub fn new(chunks: Arc<HashMap<FGuid, FileChunk>>, http: Arc<HttpService>, options: &ManifestOptions) -> Self {
         Self {
             chunks, 
             http,
-             cache_dir
+            cache: options.chunk_cache.clone().or_else(|| options.cache_directory.as_ref()
+                .map(|dir| Arc::new(FileSystemCache::new(Path::new(dir), options.shard_cache)) as Arc<dyn ChunkCache>)),
+            verify_chunks: options.verify_chunks,
+            offline: options.offline,
+            max_retries: options.max_retries,
+            max_concurrent_downloads: options.max_concurrent_downloads.max(1),
+            download_semaphore: Semaphore::new(options.max_concurrent_downloads.max(1)),
+            rate_limiter: options.max_bytes_per_second.map(RateLimiter::new),
+            in_flight: Mutex::new(HashMap::new()),
+            memory_cache: Some(options.chunk_memory_cache_size).filter(|size| *size > 0).map(ChunkMemoryCache::new),
+            stats: DownloadCounters::default(),
+            failed_hosts: Mutex::new(HashSet::new())
+        }
+    }
+
+    /// How many chunks were loaded from the cache and downloaded since the manifest was parsed.
+    pub fn download_stats(&self) -> DownloadStats {
+        self.stats.snapshot()
+    }
+
+    /// # Errors
+    /// 
+    /// Will return `Err` if the SHA1 of the decompressed chunk data doesn't match the manifest
+    pub fn verify_chunk(&self, guid: &FGuid, data: &[u8]) -> Result<()> {
+        let chunk = match self.chunks.get(guid) {
+            Some(chunk) => chunk,
+            None => return Err(Box::new(ParserError::new(&format!("unknown chunk {}", guid))))
+        };
+
+        let sha = chunk.sha.parse::<ChunkSha>()?;
+        if !sha.matches(data) {
+            return Err(Box::new(DownloadError::ShaMismatch {
+                guid: *guid,
+                expected: sha.to_string(),
+                actual: hash::to_hex(&hash::sha1(data))
+            }));
+        }
+
+        Ok(())
+    }
+
+    // with `offline` set a chunk that isn't cached is an error instead of a download
+    fn ensure_online(&self, guid: &FGuid) -> Result<()> {
+        if self.offline {
+            return Err(Box::new(ParserError::new(&format!("chunk {} isn't cached and downloading is disabled in offline mode", guid))));
+        }
+
+        Ok(())
+    }
+
+    // `None` if caching is disabled or the chunk isn't cached
+    async fn read_cache(&self, guid: &FGuid) -> Result<Option<Vec<u8>>> {
+        match (&self.cache, self.chunks.get(guid)) {
+            (Some(cache), Some(chunk)) => Ok(cache.get(chunk).await.map_err(DownloadError::Cache)?),
+            _ => Ok(None)
         }
     }
+
+    async fn write_cache(&self, guid: &FGuid, data: &[u8]) -> Result<()> {
+        if let (Some(cache), Some(chunk)) = (&self.cache, self.chunks.get(guid)) {
+            cache.put(chunk, data).await.map_err(DownloadError::Cache)?;
+        }
+
+        Ok(())
+    }
+
+    /// Chunks that aren't in the cache yet, ordered by GUID. When `verify_chunks` is enabled,
+    /// cached chunks that fail the SHA1 check are reported as missing as well.
+    pub async fn missing_chunks(&self) -> Vec<FGuid> {
+        let mut missing: Vec<FGuid> = stream::iter(self.chunks.values())
+            .map(|chunk| async move { (!self.is_cached(chunk).await).then_some(chunk.guid) })
+            .buffer_unordered(self.max_concurrent_downloads)
+            .filter_map(|guid| async move { guid })
+            .collect()
+            .await;
+
+        missing.sort();
+        missing
+    }
+
+    async fn is_cached(&self, chunk: &FileChunk) -> bool {
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => return false
+        };
+
+        if !self.verify_chunks {
+            return cache.contains(chunk).await;
+        }
+
+        match cache.get(chunk).await {
+            Ok(Some(data)) => self.verify_chunk(&chunk.guid, &data).is_ok(),
+            _ => false
+        }
+    }
+
+    /// Downloads the chunk into the cache, unless it's already cached (and valid when
+    /// `verify_chunks` is enabled).
+    /// 
+    /// # Errors
+    /// 
+    /// Will return `Err` if no cache is configured, the chunk is unknown or fails to download
+    pub async fn cache_chunk(&self, guid: &FGuid) -> Result<()> {
+        let chunk = match self.chunks.get(guid) {
+            Some(chunk) => chunk,
+            None => return Err(Box::new(ParserError::new(&format!("unknown chunk {}", guid))))
+        };
+
+        if self.cache.is_none() {
+            return Err(Box::new(ParserError::new("no chunk cache configured")));
+        }
+
+        FileManifest::load_chunk(self, &ChunkDownload::whole(chunk)).await?;
+        Ok(())
+    }
+
+    /// Downloads the chunk file exactly as the CDN serves it, including the chunk header and without
+    /// decompressing or verifying it.
+    /// 
+    /// # Errors
+    /// 
+    /// Will return `Err` if the chunk is unknown or the request still fails after `max_retries` retries
+    pub async fn download_raw_chunk(&self, guid: &FGuid) -> Result<Vec<u8>> {
+        let chunk = match self.chunks.get(guid) {
+            Some(chunk) => chunk,
+            None => return Err(Box::new(ParserError::new(&format!("unknown chunk {}", guid))))
+        };
+
+        self.ensure_online(guid)?;
+        let data = self.get_with_failover(&ChunkDownload::whole(chunk), None).await?;
+        self.stats.downloaded_chunks.fetch_add(1, Ordering::Relaxed);
+        Ok(data)
+    }
+
+    /// # Errors
+    /// 
+    /// Will return `Err` if the request still fails after `max_retries` retries
+    pub async fn get_with_retry(&self, uri: &str) -> Result<Vec<u8>> {
+        self.fetch(uri, None, false).await
+    }
+
+    /// Fetches bytes `start..end` of `uri`, see [`HttpService::get_range`].
+    /// 
+    /// # Errors
+    /// 
+    /// Will return `Err` if the request still fails after `max_retries` retries
+    pub async fn get_range_with_retry(&self, uri: &str, start: u64, end: u64) -> Result<Vec<u8>> {
+        self.fetch(uri, Some((start, end)), false).await
+    }
+
+    // with `fail_fast`, connection errors, client errors and timeouts aren't retried so the caller can move on to a mirror
+    async fn fetch(&self, uri: &str, range: Option<(u64, u64)>, fail_fast: bool) -> Result<Vec<u8>> {
+        let start = Instant::now();
+        let data = match local_path(uri) {
+            Some(path) => {
+                log::debug!("reading {} from local mirror", path.display());
+                read_local(&path, range).await?
+            },
+            None => self.retry(uri, fail_fast, || self.http.get_throttled(uri, range, self.rate_limiter.as_ref())).await?
+        };
+
+        self.stats.downloaded(data.len(), start.elapsed());
+        Ok(data)
+    }
+
+    // Tries the uri of the chunk, then its mirrors. A host that failed is skipped by the later downloads
+    // of the context, which also sends the data range of a part to the mirror that served its header.
+    async fn get_with_failover(&self, download: &ChunkDownload, range: Option<(u64, u64)>) -> Result<Vec<u8>> {
+        let mirrors = self.chunks.get(&download.guid).map(|chunk| chunk.mirror_uris.as_slice()).unwrap_or_default();
+        let uris: Vec<&String> = std::iter::once(&download.uri).chain(mirrors).collect();
+        for (index, uri) in uris.iter().enumerate() {
+            let mirror = uris.get(index + 1);
+            // the last uri is tried even if its host failed before, there's nothing left to fall back to
+            if mirror.is_some() && self.has_failed(uri) {
+                continue;
+            }
+
+            let result = self.fetch(uri, range, mirror.is_some()).await;
+            if let Err(error) = &result {
+                self.mark_failed(uri);
+                if let Some(mirror) = mirror {
+                    log::warn!("chunk {} failed at {}, trying {}: {}", download.guid, uri, mirror, error);
+                    continue;
+                }
+            }
+
+            return result;
+        }
+
+        Err(Box::new(ParserError::new(&format!("chunk {} has no uri", download.guid))))
+    }
+
+    fn has_failed(&self, uri: &str) -> bool {
+        host(uri).is_some_and(|host| self.failed_hosts.lock().unwrap().contains(&host))
+    }
+
+    fn mark_failed(&self, uri: &str) {
+        if let Some(host) = host(uri) {
+            self.failed_hosts.lock().unwrap().insert(host);
+        }
+    }
+
+    async fn retry<F, Fut>(&self, uri: &str, fail_fast: bool, request: F) -> Result<Vec<u8>>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<Vec<u8>>>
+    {
+        let mut attempt = 0;
+        loop {
+            let result = {
+                // a download waiting for the bandwidth of others doesn't need a permit yet
+                if let Some(rate_limiter) = &self.rate_limiter {
+                    rate_limiter.ready().await;
+                }
+
+                let _permit = self.download_semaphore.acquire().await?;
+                request().await
+            };
+
+            match result {
+                Ok(data) => return Ok(data),
+                Err(error) if !http::is_transient(error.as_ref()) || (fail_fast && should_fail_over(error.as_ref())) => {
+                    log::warn!("request to {} failed: {}", uri, error);
+                    return Err(Box::new(DownloadError::Network(error)));
+                },
+                Err(error) if attempt >= self.max_retries => {
+                    log::warn!("giving up on {} after {} attempts: {}", uri, attempt + 1, error);
+                    return Err(Box::new(DownloadError::Network(error)));
+                },
+                Err(error) => {
+                    log::warn!("retrying {} after error (attempt {} of {}): {}", uri, attempt + 1, self.max_retries + 1, error);
+                    tokio::time::sleep(retry_delay(attempt)).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+}
+
+// Exponential backoff with up to half of the delay as jitter, so the downloads that failed together
+// don't all retry at the same moment
+fn retry_delay(attempt: u32) -> Duration {
+    let delay = RETRY_BASE_DELAY_MS.saturating_mul(1 << attempt.min(16)).min(RETRY_MAX_DELAY_MS);
+    let jitter = RandomState::new().build_hasher().finish() % (delay / 2 + 1);
+    Duration::from_millis(delay - jitter)
+}
+
+// A host that can't be reached, refuses the request or doesn't answer in time is left for a mirror right away
+fn should_fail_over(error: &(dyn std::error::Error + 'static)) -> bool {
+    if let Some(error) = error.downcast_ref::<hyper::Error>() {
+        return error.is_connect();
+    }
+
+    match error.downcast_ref::<HttpError>() {
+        Some(HttpError::Status { status, .. }) => status.is_client_error(),
+        Some(HttpError::Timeout { .. }) => true,
+        None => false
+    }
+}
+
+// Scheme and authority of `uri`, mirrors on disk have none
+fn host(uri: &str) -> Option<String> {
+    let uri: hyper::Uri = uri.parse().ok()?;
+    Some(format!("{}://{}", uri.scheme_str()?, uri.authority()?))
+}
+
+// `file://` uris point to a chunk mirror on disk, file:///C:/mirror becomes C:/mirror on Windows
+fn local_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let path = match path.strip_prefix('/') {
+        Some(rest) if rest.get(1..2) == Some(":") => rest,
+        _ => path
+    };
+
+    Some(PathBuf::from(path))
+}
+
+// Like a range request, a range past the end of the file is cut short
+async fn read_local(path: &Path, range: Option<(u64, u64)>) -> Result<Vec<u8>> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    let read = async {
+        let mut file = tokio::fs::File::open(path).await?;
+        let mut data = Vec::new();
+        match range {
+            Some((start, end)) => {
+                file.seek(SeekFrom::Start(start)).await?;
+                file.take(end.saturating_sub(start)).read_to_end(&mut data).await?;
+            },
+            None => {
+                file.read_to_end(&mut data).await?;
+            }
+        }
+
+        Ok::<_, std::io::Error>(data)
+    };
+
+    read.await.map_err(|error| ParserError::new(&format!("failed to read {}: {}", path.display(), error)).into())
 }
 
 pub struct ChunkDownload {
+    pub guid: FGuid,
     pub uri: String,
     pub offset: usize,
     pub size: usize,
@@ -85,142 +585,458 @@ pub struct ChunkDownload {
 }
 
 impl ChunkDownload {
-    pub fn new(part: &FileChunkPart, context: Arc<ManifestContext>, position: usize) -> Self {
-        let chunk = context.chunks.get(&part.guid).unwrap();
-        Self {
+    /// # Errors
+    /// 
+    /// Will return `Err` if the part references an unknown chunk or has a negative offset or size
+    pub fn new(part: &FileChunkPart, context: &ManifestContext, position: usize) -> Result<Self> {
+        let chunk = match context.chunks.get(&part.guid) {
+            Some(chunk) => chunk,
+            None => return Err(Box::new(ParserError::new(&format!("unknown chunk {}", part.guid))))
+        };
+
+        Ok(Self {
+            guid: part.guid,
             uri: chunk.uri.clone(),
             file_name: chunk.file_name.clone(),
-            offset: usize::try_from(part.offset).unwrap(),
-            size: usize::try_from(part.size).unwrap(),
+            offset: usize::try_from(part.offset)?,
+            size: usize::try_from(part.size)?,
             position
+        })
+    }
+
+    // only the chunk itself matters when it isn't read as part of a file
+    fn whole(chunk: &FileChunk) -> Self {
+        Self {
+            guid: chunk.guid,
+            uri: chunk.uri.clone(),
+            file_name: chunk.file_name.clone(),
+            offset: 0,
+            size: 0,
+            position: 0
         }
     }
 }
 
-type ChunkDownloadResult = (ChunkDownload, Vec<u8>);
+/// A downloaded chunk part together with the decompressed data of its whole chunk.
+pub type ChunkDownloadResult = (ChunkDownload, Bytes);
 
-#[derive(Debug)]
+// Chunk downloads spawned for a single file, aborted when dropped so a failed
+// or abandoned save doesn't leave tasks running in the background
+struct PendingDownloads {
+    receiver: mpsc::UnboundedReceiver<Result<ChunkDownloadResult>>,
+    handles: Vec<JoinHandle<()>>,
+    remaining: usize,
+    total_size: usize
+}
+
+impl Stream for PendingDownloads {
+    type Item = Result<ChunkDownloadResult>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.remaining == 0 {
+            return Poll::Ready(None);
+        }
+
+        match self.receiver.poll_recv(cx) {
+            Poll::Ready(Some(received)) => {
+                self.remaining -= 1;
+                Poll::Ready(Some(received))
+            },
+            Poll::Ready(None) => {
+                self.remaining = 0;
+                Poll::Ready(Some(Err(Box::new(ParserError::new("chunk download stopped without a result")))))
+            },
+            Poll::Pending => Poll::Pending
+        }
+    }
+}
+
+impl Drop for PendingDownloads {
+    fn drop(&mut self) {
+        for handle in &self.handles {
+            handle.abort();
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub struct FileManifest {
     pub name: String,
     pub hash: String,
     pub install_tags: Vec<String>,
     pub chunk_parts: Vec<FileChunkPart>,
+    #[serde(skip)]
     pub context: Arc<ManifestContext>,
     pub size: usize,
 }
 
 impl FileManifest {
 
-    pub fn new(name: String, hash: String, install_tags: Vec<String>, chunk_parts: Vec<FileChunkPart>, context: Arc<ManifestContext>) -> Self {
+    /// # Errors
+    /// 
+    /// Will return `Err` if a chunk part has a negative offset or size, or the parts add up to more than fits in memory
+    pub fn new(name: String, hash: String, install_tags: Vec<String>, chunk_parts: Vec<FileChunkPart>, context: Arc<ManifestContext>) -> Result<Self> {
         let mut size: usize = 0;
         for chunk_part in &chunk_parts {
-            size += usize::try_from(chunk_part.size).unwrap_or_default();
+            let (offset, part_size) = match (usize::try_from(chunk_part.offset), usize::try_from(chunk_part.size)) {
+                (Ok(offset), Ok(part_size)) => (offset, part_size),
+                _ => return Err(Box::new(ParserError::new(&format!("invalid chunk part of {} at offset {} with size {}", name, chunk_part.offset, chunk_part.size))))
+            };
+
+            size = match (size.checked_add(part_size), offset.checked_add(part_size)) {
+                (Some(size), Some(_)) => size,
+                _ => return Err(Box::new(ParserError::new(&format!("size of {} overflows", name))))
+            };
         }
 
-        Self {
+        Ok(Self {
             name,
             hash,
             install_tags, 
             chunk_parts, 
             context,
             size
+        })
+    }
+
+    /// Files without install tags, or with only an empty tag, are part of every install.
+    pub fn is_untagged(&self) -> bool {
+        self.install_tags.iter().all(String::is_empty)
+    }
+
+    /// GUIDs of the chunks the file is assembled from, without duplicates and in the order they're first used.
+    pub fn referenced_chunks(&self) -> Vec<FGuid> {
+        let mut seen = HashSet::new();
+        self.chunk_parts.iter()
+            .map(|part| part.guid)
+            .filter(|guid| seen.insert(*guid))
+            .collect()
+    }
+
+    /// Like `referenced_chunks`, returning the chunks themselves.
+    pub fn referenced_chunk_info(&self) -> Vec<&FileChunk> {
+        self.referenced_chunks().iter()
+            .filter_map(|guid| self.context.chunks.get(guid))
+            .collect()
+    }
+
+    /// # Errors
+    /// 
+    /// Will return `Err` with the kind of failure if a chunk part can't be downloaded, decompressed,
+    /// verified or cached
+    pub async fn save(&self) -> std::result::Result<Vec<u8>, DownloadError> {
+        self.save_with_progress(|_, _| {}).await
+    }
+
+    /// Like `save`, calling `progress` with the completed and total byte count after every chunk part.
+    pub async fn save_with_progress<F>(&self, progress: F) -> std::result::Result<Vec<u8>, DownloadError>
+    where
+        F: Fn(usize, usize)
+    {
+        self.save_until_cancelled(progress, CancellationToken::new()).await.map_err(DownloadError::from)
+    }
+
+    /// Like `save`, stopping the remaining chunk downloads and returning `CancelledError` once
+    /// `cancellation` is cancelled. Chunks that were already cached stay in the cache.
+    pub async fn save_cancellable(&self, cancellation: CancellationToken) -> Result<Vec<u8>> {
+        self.save_until_cancelled(|_, _| {}, cancellation).await
+    }
+
+    async fn save_until_cancelled<F>(&self, progress: F, cancellation: CancellationToken) -> Result<Vec<u8>>
+    where
+        F: Fn(usize, usize)
+    {
+        let mut downloads = self.spawn_downloads(&cancellation, true)?;
+        let total_size = downloads.total_size;
+        let mut result: Vec<u8> = vec![0u8; total_size];
+
+        let mut completed = 0;
+        while let Some(received) = downloads.next().await {
+            let (download, data) = received?;
+            let block = &mut result[download.position..download.position+download.size];
+
+            block.copy_from_slice(&data);
+
+            completed += download.size;
+            progress(completed, total_size);
+        }
+
+        Ok(result)
+    }
+
+    /// Writes the file into `writer` as chunks complete, so only individual chunks are held in memory.
+    pub async fn save_to<W>(&self, writer: &mut W) -> Result<()>
+    where
+        W: Write + Seek
+    {
+        let mut downloads = self.spawn_downloads(&CancellationToken::new(), true)?;
+        while let Some(received) = downloads.next().await {
+            let (download, data) = received?;
+
+            writer.seek(SeekFrom::Start(u64::try_from(download.position)?))?;
+            writer.write_all(&data)?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Like `save_to`, writing with async I/O, e.g. into a `tokio::fs::File`.
+    pub async fn save_to_async<W>(&self, writer: &mut W) -> Result<()>
+    where
+        W: AsyncWrite + AsyncSeek + Unpin
+    {
+        use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+        let mut downloads = self.spawn_downloads(&CancellationToken::new(), true)?;
+        while let Some(received) = downloads.next().await {
+            let (download, data) = received?;
+
+            writer.seek(SeekFrom::Start(u64::try_from(download.position)?)).await?;
+            writer.write_all(&data).await?;
         }
+
+        writer.flush().await?;
+        Ok(())
     }
 
-    pub async fn save(&self) -> Result<Vec<u8>> {
+    /// Reads the file from start to end, downloading chunk parts ahead of the read position.
+    /// Parts that finish early are held back until every part before them has been read.
+    /// 
+    /// # Errors
+    /// 
+    /// Will return `Err` if a chunk part references an unknown chunk
+    pub fn reader(&self) -> Result<impl AsyncRead + Send + Unpin> {
+        let downloads = self.create_downloads()?;
+        let context = self.context.clone();
+        let concurrency = context.max_concurrent_downloads;
+
+        let parts = stream::iter(downloads)
+            .map(move |download| Self::read_part(context.clone(), download))
+            .buffered(concurrency);
+
+        Ok(StreamReader::new(parts))
+    }
+
+    async fn read_part(context: Arc<ManifestContext>, download: ChunkDownload) -> std::io::Result<Bytes> {
+        Self::load_part(&context, &download).await.map_err(std::io::Error::other)
+    }
+
+    fn create_downloads(&self) -> Result<Vec<ChunkDownload>> {
         let mut downloads = Vec::with_capacity(self.chunk_parts.len());
         let mut position = 0;
         for chunk_part in &self.chunk_parts {
-            let download = ChunkDownload::new(chunk_part, self.context.clone(), position);
+            let download = ChunkDownload::new(chunk_part, &self.context, position)?;
             position += download.size;
 
             downloads.push(download);
         }
-        
+
+        Ok(downloads)
+    }
+
+    /// Starts downloading every chunk part of the file and yields them in the order they complete.
+    /// Dropping the stream stops the downloads that are still running.
+    /// 
+    /// # Errors
+    /// 
+    /// Will return `Err` if a chunk part references an unknown chunk
+    pub fn chunk_stream(&self) -> Result<impl Stream<Item = Result<ChunkDownloadResult>> + Unpin> {
+        self.spawn_downloads(&CancellationToken::new(), false)
+    }
+
+    /// With `parts_only` the downloads yield just the bytes of their part instead of the whole chunk.
+    fn spawn_downloads(&self, cancellation: &CancellationToken, parts_only: bool) -> Result<PendingDownloads> {
+        let downloads = self.create_downloads()?;
         let total_size: usize = downloads.iter().map(|f| f.size).sum();
-        let mut result: Vec<u8> = vec![0u8; total_size];
-        let (tx, rx) = mpsc::channel();
+        let remaining = downloads.len();
+        let (sender, rx) = mpsc::unbounded_channel();
+
+        let mut handles = Vec::with_capacity(remaining);
+        for download in downloads {
+            let future = Self::download_chunk(self.context.clone(), download, sender.clone(), cancellation.clone(), parts_only);
+            handles.push(tokio::spawn(future));
+        }
+
+        Ok(PendingDownloads {
+            receiver: rx,
+            handles,
+            remaining,
+            total_size
+        })
+    }
+
+    async fn download_chunk(context: Arc<ManifestContext>, download: ChunkDownload, sender: mpsc::UnboundedSender<Result<ChunkDownloadResult>>,
+        cancellation: CancellationToken, parts_only: bool) {
+        let load = async {
+            if parts_only {
+                Self::load_part(&context, &download).await
+            } else {
+                Self::load_chunk(&context, &download).await
+            }
+        };
 
-        // todo: pool
-        {
-            let sender = Arc::new(Mutex::new(tx));
-            for download in downloads {
+        let result = tokio::select! {
+            _ = cancellation.cancelled() => Err(CancelledError.into()),
+            result = load => result
+        };
+
+        // the receiver is gone once another chunk failed, nothing left to report to
+        let _ = sender.send(result.map(|data| (download, data)));
+    }
 
-                let future = Self::download_chunk(self.context.clone(), download, sender.clone());
-                tokio::spawn(future);
+    /// Loads only the bytes of the part. Without a cache or verification the rest of the chunk
+    /// isn't needed, so uncompressed chunks are fetched with range requests covering just the part.
+    async fn load_part(context: &ManifestContext, download: &ChunkDownload) -> Result<Bytes> {
+        if context.cache.is_none() && !context.verify_chunks && !context.offline {
+            if let Some(data) = Self::fetch_part(context, download).await? {
+                return Ok(Bytes::from(data));
             }
         }
 
-        while let Ok((download, data)) = rx.recv() {
-            let start = download.offset;
-            let end = start + download.size;
-            let data = &data.as_slice()[start..end];
-            let block_ref: &mut [u8] = &mut result.as_mut();
-            let block = &mut block_ref[download.position..download.position+download.size];
+        let data = Self::load_chunk(context, download).await?;
+        match download.offset.checked_add(download.size) {
+            Some(end) if end <= data.len() => Ok(data.slice(download.offset..end)),
+            _ => Err(Box::new(ParserError::new(&format!("chunk {} is smaller than its part", download.guid))))
+        }
+    }
 
-            block.copy_from_slice(data);
+    /// Returns `None` if the chunk is compressed, as the part can't be located without decompressing the whole chunk.
+    async fn fetch_part(context: &ManifestContext, download: &ChunkDownload) -> Result<Option<Vec<u8>>> {
+        if download.size == 0 {
+            return Ok(Some(Vec::new()));
         }
 
-        Ok(result)
-    }
+        log::debug!("downloading {} bytes at {} of chunk {} from {}", download.size, download.offset, download.guid, download.uri);
+        let data = context.get_with_failover(download, Some((0, CHUNK_HEADER_MAX_SIZE))).await?;
+        let header = ChunkHeader::new(&mut data.as_slice())?;
+        Self::check_header(context, &header, download)?;
+        if header.storage()? != ChunkStorage::Raw {
+            return Ok(None);
+        }
 
-    async fn download_chunk(context: Arc<ManifestContext>, download: ChunkDownload, sender: Arc<Mutex<mpsc::Sender<ChunkDownloadResult>>>) {
-        Self::download_chunk_result(context, download, sender).await.unwrap();
+        let start = u64::try_from(download.offset)?.checked_add(u64::from(header.header_size));
+        let end = start.and_then(|start| start.checked_add(u64::try_from(download.size).ok()?));
+        let (start, end) = match (start, end) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return Err(Box::new(ParserError::new(&format!("part of chunk {} is out of range", download.guid))))
+        };
+        let data = context.get_with_failover(download, Some((start, end))).await?;
+        if data.len() != download.size {
+            return Err(Box::new(ParserError::new(&format!("chunk {} is smaller than its part", download.guid))));
+        }
+
+        context.stats.downloaded_chunks.fetch_add(1, Ordering::Relaxed);
+        Ok(Some(data))
     }
 
-    async fn download_chunk_result(context: Arc<ManifestContext>, download: ChunkDownload, sender: Arc<Mutex<mpsc::Sender<ChunkDownloadResult>>>)
-        -> Result<()> {
-        if let Some(cache_dir) = &context.cache_dir {
-            let mut path = PathBuf::new();
-            path.push(cache_dir);
-            path.push(&download.file_name);
+    async fn load_chunk(context: &ManifestContext, download: &ChunkDownload) -> Result<Bytes> {
+        if let Some(data) = context.memory_cache.as_ref().and_then(|cache| cache.get(&download.guid)) {
+            log::debug!("memory cache hit for chunk {}", download.guid);
+            context.stats.cache_hit(data.len());
+            return Ok(data);
+        }
 
-            if path.as_path().exists() {
-                let download: ChunkDownloadResult = (download, std::fs::read(path)?);
-                let sender = sender.lock().unwrap();
-                sender.send(download)?;
-            
-                return Ok(());
-            }
+        let data = Self::load_uncached_chunk(context, download).await?;
+        if let Some(cache) = &context.memory_cache {
+            cache.insert(download.guid, data.clone());
         }
 
-        let data = context.http.get(&download.uri).await?;
-        let size = data.len();
-        let mut cursor = Cursor::new(data);
+        Ok(data)
+    }
 
-        cursor.seek(SeekFrom::Start(8))?;
-        let header_size = cursor.read_i32_le()?;
+    async fn load_uncached_chunk(context: &ManifestContext, download: &ChunkDownload) -> Result<Bytes> {
+        if let Some(data) = context.read_cache(&download.guid).await? {
+            // a corrupted cached chunk is downloaded again and overwritten below
+            match context.verify_chunks.then(|| context.verify_chunk(&download.guid, &data)) {
+                Some(Err(error)) => log::warn!("discarding cached chunk {}: {}", download.guid, error),
+                _ => {
+                    log::debug!("cache hit for chunk {}", download.guid);
+                    context.stats.cache_hit(data.len());
+                    return Ok(Bytes::from(data));
+                }
+            }
+        }
 
-        cursor.seek(SeekFrom::Start(40))?;
-        let is_compressed = cursor.read_u8()? == 1;
-        cursor.seek(SeekFrom::Start(u64::try_from(header_size)?))?;
+        context.ensure_online(&download.guid)?;
 
-        let pos_size = usize::try_from(cursor.position())?;
-        let chunk_data_size = size - pos_size;
-        let compressed_data = &cursor.get_ref()[pos_size..pos_size+chunk_data_size];
+        // parts of the same chunk requested at the same time share a single download
+        let cell = context.in_flight.lock().unwrap()
+            .entry(download.guid)
+            .or_default()
+            .clone();
+        let result = cell.get_or_try_init(|| Self::fetch_chunk(context, download)).await.cloned();
 
-        let mut _result: Vec<u8> = Vec::new();
-        if is_compressed {
-            _result = decompress_to_vec_zlib(compressed_data).unwrap();
-        } else {
-            _result = compressed_data.to_vec();
+        let mut in_flight = context.in_flight.lock().unwrap();
+        if in_flight.get(&download.guid).is_some_and(|entry| Arc::ptr_eq(entry, &cell)) {
+            in_flight.remove(&download.guid);
         }
 
-        if let Some(cache_dir) = &context.cache_dir {
-            let mut path = PathBuf::new();
-            path.push(cache_dir);
-            path.push(&download.file_name);
+        result
+    }
 
-            std::fs::write(path, &_result)?;
+    // a wrong chunk behind the uri would otherwise end up in the file, e.g. with a misconfigured
+    // base uri or a poisoned CDN cache
+    fn check_header(context: &ManifestContext, header: &ChunkHeader, download: &ChunkDownload) -> Result<()> {
+        if header.guid != download.guid {
+            return Err(Box::new(ParserError::new(&format!("expected chunk {}, got {}", download.guid, header.guid))));
         }
 
-        let download: ChunkDownloadResult = (download, _result);
-        let sender = sender.lock().unwrap();
-        sender.send(download)?;
+        let chunk = match context.chunks.get(&download.guid) {
+            Some(chunk) => chunk,
+            None => return Err(Box::new(ParserError::new(&format!("unknown chunk {}", download.guid))))
+        };
+
+        // file data has no rolling hash to check against
+        if !chunk.hash.is_empty() && u64::from_str_radix(&chunk.hash, 16)? != header.hash {
+            return Err(Box::new(ParserError::new(&format!("hash mismatch for chunk {}: expected {}, got {:016X}", download.guid, chunk.hash, header.hash))));
+        }
 
         Ok(())
     }
 
+    async fn fetch_chunk(context: &ManifestContext, download: &ChunkDownload) -> Result<Bytes> {
+        log::debug!("downloading chunk {} from {}", download.guid, download.uri);
+        let data = context.get_with_failover(download, None).await?;
+        let header = ChunkHeader::new(&mut data.as_slice())?;
+        if let Err(error) = Self::check_header(context, &header, download) {
+            log::warn!("unexpected chunk behind {}: {}", download.uri, error);
+            return Err(error);
+        }
+
+        let compressed_data = match data.get(usize::try_from(header.header_size)?..) {
+            Some(compressed_data) => compressed_data,
+            None => return Err(Box::new(ParserError::new(&format!("chunk {} is smaller than its header", download.guid))))
+        };
+
+        let _result = match header.storage()? {
+            ChunkStorage::Raw => compressed_data.to_vec(),
+            ChunkStorage::Zlib => decompress_to_vec_zlib(compressed_data)
+                .map_err(|e| DownloadError::Decompression { guid: download.guid, message: format!("{:?} after {} of {} compressed bytes",
+                    e, compressed_data.len(), header.data_size_compressed) })?
+        };
+
+        // a truncated response can still inflate cleanly, v3 headers know the real size
+        if let (ChunkStorage::Zlib, Some(expected)) = (header.storage()?, header.data_size_uncompressed) {
+            if _result.len() != usize::try_from(expected)? {
+                return Err(Box::new(DownloadError::Decompression { guid: download.guid,
+                    message: format!("got {} bytes, expected {}", _result.len(), expected) }));
+            }
+        }
+
+        if context.verify_chunks {
+            if let Err(error) = context.verify_chunk(&download.guid, &_result) {
+                log::warn!("downloaded chunk {} from {} is corrupted: {}", download.guid, download.uri, error);
+                return Err(error);
+            }
+        }
+
+        context.write_cache(&download.guid, &_result).await?;
+
+        context.stats.downloaded_chunks.fetch_add(1, Ordering::Relaxed);
+        Ok(Bytes::from(_result))
+    }
+
 }
 
 pub struct FileManifestBuilder {
@@ -263,7 +1079,10 @@ impl FileManifestBuilder {
         self
     }
 
-    pub fn build(self, context: Arc<ManifestContext>) -> FileManifest {
+    /// # Errors
+    /// 
+    /// Will return `Err` if the chunk parts are invalid, see `FileManifest::new`
+    pub fn build(self, context: Arc<ManifestContext>) -> Result<FileManifest> {
         FileManifest::new(
             self.name, 
             self.hash.unwrap_or_default(), 
@@ -272,4 +1091,144 @@ impl FileManifestBuilder {
             context)
     }
 
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUID: &str = "33333333000022220000333300000003";
+    // SHA1 of "abc"
+    const ABC_SHA: &str = "A9993E364706816ABA3E25717850C26C9CD0D89D";
+
+    // feeds the downloads from memory, without a server
+    #[derive(Debug, Default)]
+    struct TestCache {
+        chunks: Mutex<HashMap<FGuid, Vec<u8>>>
+    }
+
+    #[async_trait::async_trait]
+    impl ChunkCache for TestCache {
+        async fn get(&self, chunk: &FileChunk) -> std::io::Result<Option<Vec<u8>>> {
+            Ok(self.chunks.lock().unwrap().get(&chunk.guid).cloned())
+        }
+
+        async fn put(&self, chunk: &FileChunk, data: &[u8]) -> std::io::Result<()> {
+            self.chunks.lock().unwrap().insert(chunk.guid, data.to_vec());
+            Ok(())
+        }
+    }
+
+    fn context<I>(chunks: I, options: &ManifestOptions) -> ManifestContext
+    where
+        I: IntoIterator<Item = FileChunk>
+    {
+        let chunks = Arc::new(chunks.into_iter().map(|chunk| (chunk.guid, chunk)).collect());
+        ManifestContext::new(chunks, Arc::new(HttpService::new()), options)
+    }
+
+    #[test]
+    fn verify_chunk_rejects_wrong_sha() {
+        let guid: FGuid = GUID.parse().unwrap();
+        let chunk = FileChunk::new(guid, 3, "000000DEADBEEF02", ABC_SHA, 5, "http://localhost/ChunksV4/");
+        let context = context([chunk], &ManifestOptions::new(None, None));
+
+        assert!(context.verify_chunk(&guid, b"abc").is_ok());
+        let error = context.verify_chunk(&guid, b"abd").unwrap_err();
+        match error.downcast_ref::<DownloadError>() {
+            Some(DownloadError::ShaMismatch { guid: actual_guid, expected, .. }) => {
+                assert_eq!(*actual_guid, guid);
+                assert_eq!(expected, ABC_SHA);
+            },
+            _ => panic!("expected a sha mismatch, got {}", error)
+        }
+    }
+
+    #[test]
+    fn retry_delay_is_capped() {
+        assert!(retry_delay(0) <= Duration::from_millis(RETRY_BASE_DELAY_MS));
+        for attempt in [6, 16, 17, 1000, u32::MAX] {
+            let delay = retry_delay(attempt);
+            assert!(delay <= Duration::from_millis(RETRY_MAX_DELAY_MS));
+            assert!(delay >= Duration::from_millis(RETRY_MAX_DELAY_MS / 2));
+        }
+    }
+
+    #[test]
+    fn fails_over_on_client_errors_and_timeouts() {
+        let status = |status| HttpError::Status { uri: "http://localhost/".to_owned(), status };
+        assert!(should_fail_over(&status(hyper::StatusCode::NOT_FOUND)));
+        assert!(should_fail_over(&status(hyper::StatusCode::FORBIDDEN)));
+        assert!(should_fail_over(&HttpError::Timeout { uri: "http://localhost/".to_owned(), timeout: Duration::from_secs(1) }));
+        assert!(!should_fail_over(&status(hyper::StatusCode::SERVICE_UNAVAILABLE)));
+        assert!(!should_fail_over(&ParserError::new("invalid chunk")));
+    }
+
+    #[test]
+    fn failed_hosts_are_remembered() {
+        let context = context([], &ManifestOptions::new(None, None));
+        context.mark_failed("https://cdn.example.com/ChunksV4/05/000000DEADBEEF02_33333333000022220000333300000003.chunk");
+
+        assert!(context.has_failed("https://cdn.example.com/ChunksV4/01/000000DEADBEEF00_11111111000022220000333300000001.chunk"));
+        assert!(!context.has_failed("http://cdn.example.com/ChunksV4/01/000000DEADBEEF00_11111111000022220000333300000001.chunk"));
+        assert!(!context.has_failed("https://mirror.example.com/ChunksV4/01/000000DEADBEEF00_11111111000022220000333300000001.chunk"));
+    }
+
+    #[test]
+    fn verify_chunk_rejects_unknown_chunk() {
+        let guid: FGuid = GUID.parse().unwrap();
+        let chunk = FileChunk::new(guid, 3, "000000DEADBEEF02", ABC_SHA, 5, "http://localhost/ChunksV4/");
+        let context = context([chunk], &ManifestOptions::new(None, None));
+
+        assert!(context.verify_chunk(&"00000000000000000000000000000001".parse().unwrap(), b"abc").is_err());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn save_completes_on_current_thread_runtime() {
+        let cache = TestCache::default();
+        let mut chunks = Vec::new();
+        let mut chunk_parts = Vec::new();
+        for (index, data) in [&b"first chunk"[..], &b"second chunk"[..]].into_iter().enumerate() {
+            let guid: FGuid = format!("{:032X}", index + 1).parse().unwrap();
+            let sha = hash::to_hex(&hash::sha1(data));
+            chunks.push(FileChunk::new(guid, data.len() as u64, "0000000000000000", &sha, 0, "http://localhost/ChunksV4/"));
+            cache.chunks.lock().unwrap().insert(guid, data.to_vec());
+            chunk_parts.push(FileChunkPart { guid, offset: 2, size: 7 });
+        }
+
+        // offline, a chunk missing from the cache fails the save instead of waiting on the network
+        let mut options = ManifestOptions::new(None, None);
+        options.set_offline(true).set_chunk_cache(Arc::new(cache));
+        let context = Arc::new(context(chunks, &options));
+        let file = FileManifest::new("file".to_owned(), String::new(), Vec::new(), chunk_parts, context).unwrap();
+
+        assert_eq!(file.save().await.unwrap(), b"rst chucond ch");
+    }
+
+    #[test]
+    fn file_manifest_rejects_negative_parts() {
+        let guid: FGuid = GUID.parse().unwrap();
+        let context = Arc::new(context([], &ManifestOptions::new(None, None)));
+        for (offset, size) in [(-1, 3), (0, -1), (i32::MIN, i32::MAX)] {
+            let parts = vec![FileChunkPart { guid, offset, size }];
+            assert!(FileManifest::new("file".to_owned(), String::new(), Vec::new(), parts, context.clone()).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn save_rejects_parts_past_the_chunk() {
+        let guid: FGuid = GUID.parse().unwrap();
+        let cache = TestCache::default();
+        cache.chunks.lock().unwrap().insert(guid, b"abc".to_vec());
+        let mut options = ManifestOptions::new(None, None);
+        options.set_offline(true).set_chunk_cache(Arc::new(cache));
+        let chunk = FileChunk::new(guid, 3, "000000DEADBEEF02", ABC_SHA, 5, "http://localhost/ChunksV4/");
+        let context = Arc::new(context([chunk], &options));
+
+        for (offset, size) in [(2, 2), (3, 1), (i32::MAX, i32::MAX)] {
+            let parts = vec![FileChunkPart { guid, offset, size }];
+            let file = FileManifest::new("file".to_owned(), String::new(), Vec::new(), parts, context.clone()).unwrap();
+            assert!(file.save().await.is_err(), "part at {} with size {} was read", offset, size);
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,94 @@
+use sha1::{Digest, Sha1};
+
+use std::io::{Read, Write};
+
+pub(crate) type Sha1Digest = [u8; 20];
+
+/// Streaming SHA1, hashes are stored by the crate as upper case hex strings.
+#[derive(Default)]
+pub(crate) struct Sha1Hasher {
+    inner: Sha1
+}
+
+impl Sha1Hasher {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    pub fn finalize(self) -> Sha1Digest {
+        self.inner.finalize().into()
+    }
+
+}
+
+// lets `std::io::copy` feed readers into the hasher
+impl Write for Sha1Hasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+pub(crate) fn sha1(data: &[u8]) -> Sha1Digest {
+    let mut hasher = Sha1Hasher::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// Hashes everything left in `reader` without loading it at once.
+pub(crate) fn sha1_reader<R: Read>(reader: &mut R) -> std::io::Result<Sha1Digest> {
+    let mut hasher = Sha1Hasher::new();
+    std::io::copy(reader, &mut hasher)?;
+    Ok(hasher.finalize())
+}
+
+pub(crate) fn to_hex(digest: &Sha1Digest) -> String {
+    hex::encode_upper(digest)
+}
+
+/// Compares `digest` with a stored hex hash, ignoring the case of `hex`.
+pub(crate) fn matches_hex(digest: &Sha1Digest, hex: &str) -> bool {
+    to_hex(digest).eq_ignore_ascii_case(hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMPTY_SHA: &str = "DA39A3EE5E6B4B0D3255BFEF95601890AFD80709";
+    const ABC_SHA: &str = "A9993E364706816ABA3E25717850C26C9CD0D89D";
+
+    #[test]
+    fn sha1_known_vectors() {
+        assert_eq!(to_hex(&sha1(b"")), EMPTY_SHA);
+        assert_eq!(to_hex(&sha1(b"abc")), ABC_SHA);
+    }
+
+    #[test]
+    fn sha1_reader_matches_sha1() {
+        assert_eq!(to_hex(&sha1_reader(&mut std::io::empty()).unwrap()), EMPTY_SHA);
+        assert_eq!(to_hex(&sha1_reader(&mut &b"abc"[..]).unwrap()), ABC_SHA);
+
+        // longer than the buffer of `std::io::copy`
+        let data = vec![0x5A; 100_000];
+        assert_eq!(sha1_reader(&mut data.as_slice()).unwrap(), sha1(&data));
+    }
+
+    #[test]
+    fn matches_hex_ignores_case() {
+        let digest = sha1(b"abc");
+        assert!(matches_hex(&digest, ABC_SHA));
+        assert!(matches_hex(&digest, &ABC_SHA.to_lowercase()));
+        assert!(!matches_hex(&digest, EMPTY_SHA));
+        assert!(!matches_hex(&digest, &ABC_SHA[..38]));
+    }
+}
\ No newline at end of file
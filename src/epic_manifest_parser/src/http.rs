@@ -1,53 +1,371 @@
+use std::fmt::Display;
 use std::sync::Arc;
+use std::time::Duration;
 
-use hyper::{Request, Client, client::HttpConnector, Body, body::HttpBody};
-use hyper_tls::HttpsConnector;
+use hyper::{Request, Response, Client, Uri, client::{HttpConnector, ResponseFuture}, Body, body::HttpBody, header::{CONTENT_LENGTH, RANGE, USER_AGENT}, StatusCode};
+use hyper_proxy::{Intercept, Proxy, ProxyConnector};
 
-use crate::Result;
 
+use crate::{Result, ParserError, throttle::RateLimiter};
+
+pub(crate) const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+pub(crate) const DEFAULT_USER_AGENT: &str = concat!("epic_manifest_parser/", env!("CARGO_PKG_VERSION"));
+// Initial size of a response buffer when the server doesn't send a Content-Length
+pub(crate) const DEFAULT_BUFFER_CAPACITY: usize = 64 * 1024;
+
+#[cfg(all(feature = "native-tls", feature = "rustls"))]
+compile_error!("the `native-tls` and `rustls` features can't be enabled at the same time");
+#[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+compile_error!("either the `native-tls` or the `rustls` feature has to be enabled");
+
+#[cfg(feature = "native-tls")]
+type HttpsConnector = hyper_tls::HttpsConnector<HttpConnector>;
+#[cfg(feature = "rustls")]
+type HttpsConnector = hyper_rustls::HttpsConnector<HttpConnector>;
+
+pub(crate) type HttpsClient = Client<ProxyConnector<HttpsConnector>>;
+
+// OpenSSL, or SChannel and Security Framework on Windows and macOS
+#[cfg(feature = "native-tls")]
+fn https_connector() -> HttpsConnector {
+    hyper_tls::HttpsConnector::new()
+}
+
+// rustls with the root certificates of the platform
+#[cfg(feature = "rustls")]
+fn https_connector() -> HttpsConnector {
+    hyper_rustls::HttpsConnector::with_native_roots()
+}
+
+/// A request of the `HttpService` that got no successful response.
 #[derive(Debug)]
+pub enum HttpError {
+    Status { uri: String, status: StatusCode },
+    Timeout { uri: String, timeout: Duration }
+}
+
+impl HttpError {
+    /// Whether sending the request again may succeed: timeouts, server errors and rate limiting.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            HttpError::Status { status, .. } => status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS,
+            HttpError::Timeout { .. } => true
+        }
+    }
+}
+
+impl std::error::Error for HttpError { }
+
+impl Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HttpError::Status { uri, status } => write!(f, "request to {} failed with status {}", uri, status),
+            HttpError::Timeout { uri, timeout } => write!(f, "request to {} timed out after {:?}", uri, timeout)
+        }
+    }
+}
+
+// connection errors are worth retrying as well, anything else will fail the same way again
+pub(crate) fn is_transient(error: &(dyn std::error::Error + 'static)) -> bool {
+    if let Some(error) = error.downcast_ref::<HttpError>() {
+        return error.is_transient();
+    }
+
+    error.is::<hyper::Error>()
+}
+
+#[derive(Debug, Clone)]
+struct ClientConfig {
+    proxy: Option<Uri>,
+    pool_max_idle_per_host: usize,
+    pool_idle_timeout: Option<Duration>,
+    http2_only: bool,
+    http2_keep_alive: Option<(Duration, Duration)>
+}
+
+// matches the defaults of `hyper::client::Builder`
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            proxy: None,
+            pool_max_idle_per_host: usize::MAX,
+            pool_idle_timeout: Some(Duration::from_secs(90)),
+            http2_only: false,
+            http2_keep_alive: None
+        }
+    }
+}
+
+impl ClientConfig {
+    /// Builds a client that connects through the configured proxy, or through the proxies set in the
+    /// `http_proxy` and `https_proxy` environment variables when there is none.
+    fn build(&self) -> HttpsClient {
+        let mut connector = ProxyConnector::new(https_connector()).expect("failed to initialize tls");
+        match &self.proxy {
+            Some(proxy) => connector.add_proxy(Proxy::new(Intercept::All, proxy.clone())),
+            None => connector.extend_proxies(env_proxies())
+        }
+
+        let mut builder = Client::builder();
+        builder.pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .http2_only(self.http2_only);
+        if let Some((interval, timeout)) = self.http2_keep_alive {
+            builder.http2_keep_alive_interval(interval)
+                .http2_keep_alive_timeout(timeout);
+        }
+
+        builder.build(connector)
+    }
+}
+
+fn env_proxies() -> Vec<Proxy> {
+    [("http_proxy", Intercept::Http), ("https_proxy", Intercept::Https)]
+        .into_iter()
+        .filter_map(|(name, intercept)| {
+            let uri = std::env::var(name).or_else(|_| std::env::var(name.to_uppercase())).ok()?;
+            // an invalid proxy is ignored like an unset variable
+            Some(Proxy::new(intercept, uri.parse().ok()?))
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
 pub struct HttpService {
-    client: Arc<Client<HttpsConnector<HttpConnector>>>
+    client: Arc<HttpsClient>,
+    config: ClientConfig,
+    timeout: Duration,
+    user_agent: String,
+    buffer_capacity: usize
 }
 
 impl HttpService {
 
+    /// Creates a service that connects with the tls backend picked by the `native-tls` (default)
+    /// or `rustls` feature.
     pub fn new() -> Self  {
-        let connector = HttpsConnector::new();
-        let client = Client::builder()
-            .build(connector);
+        Self::with_timeout(DEFAULT_REQUEST_TIMEOUT)
+    }
+
+    /// Creates a service that fails requests when the response headers take longer than `timeout` to arrive,
+    /// or the body stops arriving for longer than `timeout`. A large body on a slow link can take longer in total.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        let config = ClientConfig::default();
 
         Self {
-            client: Arc::new(client)
+            client: Arc::new(config.build()),
+            config,
+            timeout,
+            user_agent: DEFAULT_USER_AGENT.to_owned(),
+            buffer_capacity: DEFAULT_BUFFER_CAPACITY
         }
     }
 
-    // todo: unsuccessful result
+    /// Sends `user_agent` instead of `epic_manifest_parser/<version>` with every request.
+    pub fn with_user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_owned();
+        self
+    }
+
+    /// Sets the initial size of a response buffer when the server doesn't send a `Content-Length`, 64 KiB by default.
+    pub fn with_buffer_capacity(mut self, buffer_capacity: usize) -> Self {
+        self.buffer_capacity = buffer_capacity;
+        self
+    }
+
+    /// Sends every request through the proxy at `uri` instead of the ones set in the environment.
+    /// 
+    /// # Errors
+    /// 
+    /// Will return `Err` if `uri` is not a valid uri
+    pub fn with_proxy(mut self, uri: &str) -> Result<Self> {
+        self.config.proxy = Some(uri.parse()?);
+        Ok(self.rebuild())
+    }
+
+    /// Limits how many idle connections are kept open per host, unlimited by default.
+    pub fn with_pool_max_idle_per_host(mut self, max_idle: usize) -> Self {
+        self.config.pool_max_idle_per_host = max_idle;
+        self.rebuild()
+    }
+
+    /// Sets how long an idle connection is kept open, 90 seconds by default. `None` keeps them open indefinitely.
+    pub fn with_pool_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.pool_idle_timeout = timeout;
+        self.rebuild()
+    }
+
+    /// Only speaks HTTP/2 instead of HTTP/1.1, off by default.
+    pub fn with_http2_only(mut self, http2_only: bool) -> Self {
+        self.config.http2_only = http2_only;
+        self.rebuild()
+    }
+
+    /// Pings HTTP/2 connections every `interval` and closes them when a ping isn't answered within `timeout`,
+    /// off by default.
+    pub fn with_http2_keep_alive(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.config.http2_keep_alive = Some((interval, timeout));
+        self.rebuild()
+    }
+
+    fn rebuild(mut self) -> Self {
+        self.client = Arc::new(self.config.build());
+        self
+    }
+
+    /// # Errors
+    /// 
+    /// Will return `Err` if the request fails, or an `HttpError` if it times out or the server responds with an unsuccessful status
     pub async fn get(&self, uri: &str) -> Result<Vec<u8>> {
-        let request = Request::builder()
-            .uri(uri)
-            .body(Body::empty())
-            .unwrap();
+        self.get_throttled(uri, None, None).await
+    }
 
-        let mut response = self.client.request(request).await?;        
-        let content_length: usize = match response.headers().get(hyper::header::CONTENT_LENGTH) {
-            Some(val) => val.to_str()?.parse()?,
-            None => 0,
+    /// Fetches bytes `start..end` of `uri` with a `Range` request, fewer if the body ends before `end`.
+    /// When the server ignores the range and sends the whole body, it is cut down to the range instead.
+    /// 
+    /// # Errors
+    /// 
+    /// Will return `Err` if the range is empty or the request fails, or an `HttpError` if it times out or the server responds
+    /// with an unsuccessful status
+    pub async fn get_range(&self, uri: &str, start: u64, end: u64) -> Result<Vec<u8>> {
+        self.get_throttled(uri, Some((start, end)), None).await
+    }
+
+    /// Like `get` or `get_range`, reading the body no faster than `rate_limiter` allows. Waiting for
+    /// the rate limiter doesn't count towards the timeout between two reads of the body.
+    pub(crate) async fn get_throttled(&self, uri: &str, range: Option<(u64, u64)>, rate_limiter: Option<&RateLimiter>) -> Result<Vec<u8>> {
+        if let Some((start, end)) = range.filter(|(start, end)| end <= start) {
+            return Err(Box::new(ParserError::new(&format!("invalid range {}..{} for {}", start, end, uri))));
+        }
+
+        let mut response = match tokio::time::timeout(self.timeout, self.send(uri, range)).await {
+            Ok(response) => response?,
+            Err(_) => return Err(self.timed_out(uri))
         };
-        
-        let mut result = Vec::with_capacity(std::cmp::max(content_length, 1024));
-        while let Some(chunk) = response.body_mut().data().await {
-            let chunk = chunk?;
+
+        // the timeout only catches a stalled body, a large one may take longer in total
+        let mut result = self.body_buffer(&response)?;
+        loop {
+            let chunk = match tokio::time::timeout(self.timeout, response.body_mut().data()).await {
+                Ok(Some(chunk)) => chunk?,
+                Ok(None) => break,
+                Err(_) => return Err(self.timed_out(uri))
+            };
+
+            if let Some(rate_limiter) = rate_limiter {
+                rate_limiter.acquire(chunk.len()).await;
+            }
+
             result.extend_from_slice(&chunk);
         }
 
+        if let (Some((start, end)), false) = (range, response.status() == StatusCode::PARTIAL_CONTENT) {
+            let start = usize::try_from(start)?;
+            let end = usize::try_from(end)?;
+            if result.len() < start {
+                return Err(Box::new(ParserError::new(&format!("response of {} is smaller than the range {}..{}", uri, start, end))));
+            }
+
+            // like a server honoring the range, a range past the end is cut short
+            result.truncate(end);
+            result.drain(..start);
+        }
+
         Ok(result)
     }
 
+    /// Sends `request` over the pooled connections, without the timeout and User-Agent of the service.
+    pub(crate) fn request(&self, request: Request<Body>) -> ResponseFuture {
+        self.client.request(request)
+    }
+
+    /// An empty buffer for the body of `response`, sized by its `Content-Length` if it has one.
+    pub(crate) fn body_buffer<T>(&self, response: &Response<T>) -> Result<Vec<u8>> {
+        let capacity = match response.headers().get(CONTENT_LENGTH) {
+            Some(val) => val.to_str()?.parse()?,
+            None => self.buffer_capacity
+        };
+
+        Ok(Vec::with_capacity(capacity))
+    }
+
+    // sends the GET request and checks the status, the body is left to the caller
+    async fn send(&self, uri: &str, range: Option<(u64, u64)>) -> Result<Response<Body>> {
+        let mut request = Request::builder()
+            .uri(uri)
+            .header(USER_AGENT, self.user_agent.as_str());
+        if let Some((start, end)) = range {
+            log::debug!("GET {} bytes {}..{}", uri, start, end);
+            request = request.header(RANGE, format!("bytes={}-{}", start, end - 1));
+        } else {
+            log::debug!("GET {}", uri);
+        }
+
+        let request = request.body(Body::empty())?;
+
+        let response = self.client.request(request).await?;
+        if !response.status().is_success() {
+            log::debug!("request to {} failed with status {}", uri, response.status());
+            return Err(Box::new(HttpError::Status { uri: uri.to_owned(), status: response.status() }));
+        }
+
+        Ok(response)
+    }
+
+    fn timed_out(&self, uri: &str) -> Box<dyn std::error::Error + Send + Sync> {
+        log::debug!("request to {} timed out after {:?}", uri, self.timeout);
+        Box::new(HttpError::Timeout { uri: uri.to_owned(), timeout: self.timeout })
+    }
+
 }
 
 impl Default for HttpService {
     fn default() -> Self {
         Self::new()
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+
+    // answers a single request with `body`, sleeping for the given time before each piece
+    fn serve(body: Vec<(Duration, &'static [u8])>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("http://{}/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request);
+
+            let length: usize = body.iter().map(|(_, piece)| piece.len()).sum();
+            let _ = write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", length);
+            for (delay, piece) in body {
+                std::thread::sleep(delay);
+                let _ = stream.write_all(piece).and_then(|_| stream.flush());
+            }
+        });
+
+        uri
+    }
+
+    #[tokio::test]
+    async fn timeout_only_applies_between_reads() {
+        let http = HttpService::with_timeout(Duration::from_millis(400));
+        let uri = serve(b"abcd".chunks(1).map(|piece| (Duration::from_millis(150), piece)).collect());
+
+        assert_eq!(http.get(&uri).await.unwrap(), b"abcd");
+    }
+
+    #[tokio::test]
+    async fn stalled_body_times_out() {
+        let http = HttpService::with_timeout(Duration::from_millis(400));
+        let uri = serve(vec![(Duration::ZERO, b"ab"), (Duration::from_secs(1), b"cd")]);
+
+        let error = http.get(&uri).await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<HttpError>(), Some(HttpError::Timeout { .. })), "expected a timeout, got {}", error);
+    }
 }
\ No newline at end of file
@@ -6,27 +6,44 @@
          clippy::unreadable_literal,
          clippy::too_many_lines)]
 
-use hyper::client::{Client, HttpConnector};
-use hyper::{Request, Method, Body, Response};
+use hyper::{Request, Method, Body, Response, StatusCode};
 use hyper::body::{Buf, HttpBody};
-use hyper_tls::HttpsConnector;
-use manifest::ManifestInfo;
+use manifest::{FGuid, ManifestInfo};
 use serde::Deserialize;
+use chrono::{Duration, Utc};
 
 use std::fmt::Display;
+use std::fs::OpenOptions;
+use std::io::Write;
 use std::path::{PathBuf, Path};
+use std::sync::Arc;
 
 pub mod chunk;
 pub mod manifest;
 pub mod auth;
-mod http;
+pub mod cache;
+pub mod http;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod hash;
+mod memory_cache;
+mod throttle;
 
 use crate::auth::{ClientToken, Device, AuthResponse, ExchangeCode};
+use crate::http::HttpService;
 
-pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
 const ACCOUNT_PUBLIC_SERVICE: &str = "https://account-public-service-prod.ol.epicgames.com";
 
+// Treat tokens as expired slightly early so requests don't race the expiry
+const TOKEN_EXPIRATION_GRACE_SECONDS: i64 = 30;
+
+const MAX_RATE_LIMIT_RETRIES: u32 = 3;
+// Longer waits are reported as `ClientError::RateLimited` instead of blocking the caller
+const MAX_RETRY_AFTER_SECONDS: u64 = 60;
+const DEFAULT_RETRY_AFTER_SECONDS: u64 = 1;
+
 #[derive(Debug)]
 pub struct ParserError  {
     message: String
@@ -48,6 +65,62 @@ impl Display for ParserError {
     }
 }
 
+/// Returned by cancellable downloads once their cancellation token is triggered.
+#[derive(Debug)]
+pub struct CancelledError;
+
+impl std::error::Error for CancelledError { }
+
+impl Display for CancelledError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the download was cancelled")
+    }
+}
+
+/// Why downloading a file failed, so callers can tell errors worth retrying from a corrupt cache.
+#[derive(Debug)]
+pub enum DownloadError {
+    /// A chunk request still failed after all retries.
+    Network(Box<dyn std::error::Error + Send + Sync>),
+    Decompression { guid: FGuid, message: String },
+    ShaMismatch { guid: FGuid, expected: String, actual: String },
+    /// Reading or writing the chunk cache failed.
+    Cache(std::io::Error),
+    Other(Box<dyn std::error::Error + Send + Sync>)
+}
+
+impl Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloadError::Network(error) => write!(f, "network error: {}", error),
+            DownloadError::Decompression { guid, message } => write!(f, "failed to decompress chunk {}: {}", guid, message),
+            DownloadError::ShaMismatch { guid, expected, actual } => write!(f, "sha mismatch for chunk {}: expected {}, got {}", guid, expected, actual),
+            DownloadError::Cache(error) => write!(f, "chunk cache error: {}", error),
+            DownloadError::Other(error) => write!(f, "{}", error)
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DownloadError::Network(error) | DownloadError::Other(error) => Some(error.as_ref()),
+            DownloadError::Cache(error) => Some(error),
+            _ => None
+        }
+    }
+}
+
+// the download path boxes its errors, keep the kind when one of them is a `DownloadError`
+impl From<Box<dyn std::error::Error + Send + Sync>> for DownloadError {
+    fn from(error: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        match error.downcast::<DownloadError>() {
+            Ok(error) => *error,
+            Err(error) => DownloadError::Other(error)
+        }
+    }
+}
+
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -64,36 +137,161 @@ impl Display for EpicError {
 
 impl std::error::Error for EpicError { }
 
-// todo: httpservice
+pub type ClientResult<T> = std::result::Result<T, ClientError>;
+
+#[derive(Debug)]
+pub enum ClientError {
+    Http(hyper::Error),
+    Api(EpicError),
+    Decode(serde_json::Error),
+    NotAuthenticated,
+    /// The access token expired, or the refresh token when refreshing the authentication.
+    TokenExpired,
+    Timeout,
+    /// Epic kept responding with 429, optionally asking to wait for the given duration.
+    RateLimited(Option<std::time::Duration>),
+    Other(Box<dyn std::error::Error + Send + Sync>)
+}
+
+impl Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::Http(error) => write!(f, "http error: {}", error),
+            ClientError::Api(error) => write!(f, "{}", error),
+            ClientError::Decode(error) => write!(f, "failed to decode response: {}", error),
+            ClientError::NotAuthenticated => write!(f, "the client must be authenticated."),
+            ClientError::TokenExpired => write!(f, "token expired"),
+            ClientError::Timeout => write!(f, "request timed out"),
+            ClientError::RateLimited(Some(retry_after)) => write!(f, "rate limited, retry after {:?}", retry_after),
+            ClientError::RateLimited(None) => write!(f, "rate limited"),
+            ClientError::Other(error) => write!(f, "{}", error)
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Http(error) => Some(error),
+            ClientError::Api(error) => Some(error),
+            ClientError::Decode(error) => Some(error),
+            ClientError::Other(error) => Some(error.as_ref()),
+            _ => None
+        }
+    }
+}
+
+impl From<hyper::Error> for ClientError {
+    fn from(error: hyper::Error) -> Self {
+        ClientError::Http(error)
+    }
+}
+
+impl From<EpicError> for ClientError {
+    fn from(error: EpicError) -> Self {
+        ClientError::Api(error)
+    }
+}
+
+impl From<serde_json::Error> for ClientError {
+    fn from(error: serde_json::Error) -> Self {
+        ClientError::Decode(error)
+    }
+}
+
+impl From<Box<dyn std::error::Error + Send + Sync>> for ClientError {
+    fn from(error: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        ClientError::Other(error)
+    }
+}
+
+impl From<ParserError> for ClientError {
+    fn from(error: ParserError) -> Self {
+        ClientError::Other(Box::new(error))
+    }
+}
+
+impl From<std::io::Error> for ClientError {
+    fn from(error: std::io::Error) -> Self {
+        ClientError::Other(Box::new(error))
+    }
+}
+
+impl From<hyper::http::Error> for ClientError {
+    fn from(error: hyper::http::Error) -> Self {
+        ClientError::Other(Box::new(error))
+    }
+}
+
+impl From<hyper::http::uri::InvalidUri> for ClientError {
+    fn from(error: hyper::http::uri::InvalidUri) -> Self {
+        ClientError::Other(Box::new(error))
+    }
+}
+
+impl From<hyper::header::ToStrError> for ClientError {
+    fn from(error: hyper::header::ToStrError) -> Self {
+        ClientError::Other(Box::new(error))
+    }
+}
+
+impl From<std::num::ParseIntError> for ClientError {
+    fn from(error: std::num::ParseIntError) -> Self {
+        ClientError::Other(Box::new(error))
+    }
+}
+
+impl From<serde_urlencoded::ser::Error> for ClientError {
+    fn from(error: serde_urlencoded::ser::Error) -> Self {
+        ClientError::Other(Box::new(error))
+    }
+}
+
 pub struct EpicGamesClient {
-    client: Client<HttpsConnector<HttpConnector>>,
-    auth: Option<AuthResponse>
+    http: Arc<HttpService>,
+    auth: Option<AuthResponse>,
+    client_token: Option<ClientToken>,
+    auto_refresh: bool,
+    timeout: std::time::Duration,
+    user_agent: String
 }
 
 impl EpicGamesClient {
 
     pub fn new() -> Self {
-        let client = Client::builder()
-            .build::<_, hyper::Body>(HttpsConnector::new());
+        Self::with_http_service(Arc::new(HttpService::new()))
+    }
+
+    pub fn builder() -> EpicGamesClientBuilder {
+        EpicGamesClientBuilder::new()
+    }
+
+    /// Creates a client sending its requests over the connection pool of `http`, which can also be
+    /// passed to `ManifestOptions::set_http_service` to reuse connections for chunk downloads.
+    /// The timeout and User-Agent of the client still apply to its own requests.
+    pub fn with_http_service(http: Arc<HttpService>) -> Self {
         Self {
-            client,
-            auth: None
+            http,
+            auth: None,
+            client_token: None,
+            auto_refresh: false,
+            timeout: http::DEFAULT_REQUEST_TIMEOUT,
+            user_agent: http::DEFAULT_USER_AGENT.to_owned()
         }
     }
 
     /// # Errors
     /// 
     /// Will return `Err` if the request was not successful or if the client is not authenticated yet
-    pub async fn get_manifest_info_authenticated(&self, url: &str) -> Result<ManifestInfo> {
-        self.requires_authentication()?;
+    pub async fn get_manifest_info_authenticated(&mut self, url: &str) -> ClientResult<ManifestInfo> {
+        self.ensure_authentication().await?;
 
-        let request = Request::builder()
+        let request = self.request()
             .uri(url)
             .header("Authorization", self.get_authentication_header())
             .body(Body::empty())?;
 
-        let response = self.client.request(request).await?;
-        let data = Self::process_response(response).await?;
+        let data = self.send(request).await?;
         let json = &serde_json::from_reader(data.reader())?;
 
         Ok(ManifestInfo::new(json)?)
@@ -103,19 +301,27 @@ impl EpicGamesClient {
     /// # Errors
     /// 
     /// Will return `Err` if the request was not successful
-    pub async fn get_manifest_info(&self, _url: &str) -> Result<()> {
-        todo!()
+    pub async fn get_manifest_info(&self, url: &str) -> ClientResult<ManifestInfo> {
+        let request = self.request()
+            .uri(url)
+            .body(Body::empty())?;
+
+        let data = self.send(request).await?;
+        let json = &serde_json::from_reader(data.reader())?;
+
+        Ok(ManifestInfo::new(json)?)
     }
 
     /// # Errors
     /// 
     /// Will return `Err` if the request was not successful
-    pub async fn authenticate_with_device(&mut self, device: &Device, client_token: &ClientToken) -> Result<&AuthResponse> {
+    pub async fn authenticate_with_device(&mut self, device: &Device, client_token: &ClientToken) -> ClientResult<&AuthResponse> {
         self.set_authentication(self.authenticate(client_token, 
             &[("grant_type", "device_auth"),
              ("account_id", &device.account_id),
              ("device_id", &device.device_id),
              ("secret", &device.secret)]).await?);
+        self.client_token = Some(client_token.clone());
 
         Ok(self.auth.as_ref().unwrap())
     }
@@ -123,11 +329,66 @@ impl EpicGamesClient {
     /// # Errors
     /// 
     /// Will return `Err` if the request was not successful
-    pub async fn authenticate_with_exchange(&mut self, client_token: &ClientToken) -> Result<&AuthResponse> {
+    pub async fn authenticate_with_exchange(&mut self, client_token: &ClientToken) -> ClientResult<&AuthResponse> {
         let exchange = self.get_exchange_code().await?;
         self.set_authentication(self.authenticate(client_token, 
             &[("grant_type", "exchange_code"),
              ("exchange_code", &exchange.code)]).await?);
+        self.client_token = Some(client_token.clone());
+
+        Ok(self.auth.as_ref().unwrap())
+    }
+
+    /// # Errors
+    /// 
+    /// Will return `Err` if the request was not successful
+    pub async fn authenticate_with_authorization_code(&mut self, client_token: &ClientToken, code: &str) -> ClientResult<&AuthResponse> {
+        self.set_authentication(self.authenticate(client_token,
+            &[("grant_type", "authorization_code"),
+             ("code", code)]).await?);
+        self.client_token = Some(client_token.clone());
+
+        Ok(self.auth.as_ref().unwrap())
+    }
+
+    /// # Errors
+    /// 
+    /// Will return `Err` if the request was not successful
+    pub async fn authenticate_with_client_credentials(&mut self, client_token: &ClientToken) -> ClientResult<&AuthResponse> {
+        self.set_authentication(self.authenticate(client_token,
+            &[("grant_type", "client_credentials")]).await?);
+        self.client_token = Some(client_token.clone());
+
+        Ok(self.auth.as_ref().unwrap())
+    }
+
+    /// # Errors
+    /// 
+    /// Will return `Err` if the client is not authenticated, the request was not successful or `ClientError::TokenExpired`
+    /// if the refresh token has expired
+    pub async fn refresh_authentication(&mut self, client_token: &ClientToken) -> ClientResult<&AuthResponse> {
+        let refresh_token = match &self.auth {
+            Some(auth) => {
+                let refresh_token = match &auth.refresh_token {
+                    Some(refresh_token) => refresh_token.clone(),
+                    None => return Err(ClientError::from(ParserError::new("the current authentication has no refresh token.")))
+                };
+
+                if let Some(refresh_expires_at) = auth.refresh_expires_at()? {
+                    if Utc::now() >= refresh_expires_at {
+                        return Err(ClientError::TokenExpired);
+                    }
+                }
+
+                refresh_token
+            },
+            None => return Err(ClientError::NotAuthenticated)
+        };
+
+        self.set_authentication(self.authenticate(client_token,
+            &[("grant_type", "refresh_token"),
+             ("refresh_token", &refresh_token)]).await?);
+        self.client_token = Some(client_token.clone());
 
         Ok(self.auth.as_ref().unwrap())
     }
@@ -135,49 +396,67 @@ impl EpicGamesClient {
     /// # Errors
     /// 
     /// Will return `Err` if the request was not successful or if the user was not authenticated
-    pub async fn get_exchange_code(&self) -> Result<ExchangeCode> {
-        self.requires_authentication()?;
+    pub async fn get_exchange_code(&mut self) -> ClientResult<ExchangeCode> {
+        self.ensure_authentication().await?;
 
-        let request = Request::builder()
+        let request = self.request()
             .uri(format!("{}{}", ACCOUNT_PUBLIC_SERVICE, "/account/api/oauth/exchange"))
             .header("Authorization", self.get_authentication_header())
             .body(Body::empty())?;
 
-        let response = self.client.request(request).await?;
-        let data = Self::process_response(response).await?;
+        let data = self.send(request).await?;
         let exchange: ExchangeCode = serde_json::from_reader(data.reader())?;
 
         Ok(exchange)
     }
 
-    pub async fn download_manifest_async(&self, manifest: &ManifestInfo, cache_dir: Option<&str>) -> Result<Vec<u8>> {
+    /// Downloads the manifest, reading and writing it as `ManifestInfo::cache_file_name` in `cache_dir`
+    /// if one is given.
+    pub async fn download_manifest_async(&self, manifest: &ManifestInfo, cache_dir: Option<&str>) -> ClientResult<Vec<u8>> {
+        self.download_manifest_with_progress(manifest, cache_dir, |_, _| {}).await
+    }
+
+    /// Like `download_manifest_async`, calling `progress` with the bytes received so far and the
+    /// `Content-Length` of the response, if any, as the manifest streams in. The count starts over
+    /// when a distribution point fails and the next one is tried.
+    pub async fn download_manifest_with_progress<F>(&self, manifest: &ManifestInfo, cache_dir: Option<&str>, progress: F) -> ClientResult<Vec<u8>>
+    where
+        F: Fn(u64, Option<u64>)
+    {
         if let Some(cache_dir) = cache_dir {
-            let path: PathBuf = [cache_dir, &manifest.file_name].iter().collect();
-            if path.as_path().exists() {
-                let file = std::fs::read(path)?;
-                return Ok(file);
+            let path: PathBuf = [cache_dir, &manifest.cache_file_name()].iter().collect();
+            match tokio::fs::read(path).await {
+                // a truncated or corrupted cache file is downloaded again and overwritten below
+                Ok(file) if manifest.verify(&file).is_ok() => {
+                    let size = file.len() as u64;
+                    progress(size, Some(size));
+                    return Ok(file);
+                },
+                Err(error) if error.kind() != std::io::ErrorKind::NotFound => return Err(error.into()),
+                _ => {}
             }
         }
 
-        let mut response = self.client.get(manifest.uri.parse()?).await?;
-        let content_length: usize = match response.headers().get(hyper::header::CONTENT_LENGTH) {
-            Some(val) => val.to_str()?.parse()?,
-            None => 0,
-        };
-
-        let mut result = Vec::with_capacity(std::cmp::max(content_length, 1024));
-        while let Some(chunk) = response.body_mut().data().await {
-            let chunk = chunk?;
-            result.extend_from_slice(&chunk);
+        // try every distribution point in order, the first one is the primary
+        let uris = if manifest.uris.is_empty() { std::slice::from_ref(&manifest.uri) } else { manifest.uris.as_slice() };
+        let mut result = Err(ClientError::Other(Box::new(ParserError::new("manifest has no uris"))));
+        for uri in uris {
+            result = match self.download(uri, &progress).await {
+                Ok(data) => manifest.verify(&data).map(|_| data).map_err(ClientError::from),
+                Err(error) => Err(error)
+            };
+
+            if result.is_ok() {
+                break;
+            }
         }
+        let result = result?;
 
         if let Some(cache_dir) = cache_dir {
-            if !Path::new(cache_dir).exists() {
-                std::fs::create_dir(cache_dir)?;
-            }
+            tokio::fs::create_dir_all(cache_dir).await?;
 
-            let path: PathBuf = [cache_dir, &manifest.file_name].iter().collect();
-            std::fs::write(path, &result)?;
+            let path: PathBuf = [cache_dir, &manifest.cache_file_name()].iter().collect();
+            tokio::fs::write(path, &result).await?;
         }
 
         Ok(result)
@@ -186,41 +465,255 @@ impl EpicGamesClient {
     /// # Errors
     /// 
     /// Will return `Err` if the request was not successful
-    async fn authenticate(&self, client_token: &ClientToken, parameters: &[(&str, &str)]) -> Result<AuthResponse> {
-        let request = Request::builder()
+    async fn authenticate(&self, client_token: &ClientToken, parameters: &[(&str, &str)]) -> ClientResult<AuthResponse> {
+        let request = self.request()
             .method(Method::POST)
             .uri(format!("{}{}", ACCOUNT_PUBLIC_SERVICE, "/account/api/oauth/token"))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .header("Authorization", format!("basic {}", client_token.encoded))
             .body(Body::from(serde_urlencoded::to_string(parameters)?))?;
 
-        let response = self.client.request(request).await?;
-        let data = Self::process_response(response).await?;
+        let data = self.send(request).await?;
 
         let auth: AuthResponse = serde_json::from_reader(data.reader())?;
 
         Ok(auth)
     }
 
-    async fn process_response(res: Response<Body>) -> Result<impl Buf> {
+    async fn download<F>(&self, uri: &str, progress: &F) -> ClientResult<Vec<u8>>
+    where
+        F: Fn(u64, Option<u64>)
+    {
+        let request = self.request()
+            .uri(uri)
+            .body(Body::empty())?;
+
+        let mut response = match tokio::time::timeout(self.timeout, self.http.request(request)).await {
+            Ok(response) => response?,
+            Err(_) => return Err(ClientError::Timeout)
+        };
+        if !response.status().is_success() {
+            return Err(ClientError::Other(Box::new(ParserError::new(&format!("request to {} failed with status {}", uri, response.status())))));
+        }
+
+        let mut result = self.http.body_buffer(&response)?;
+        let content_length = response.headers().get(hyper::header::CONTENT_LENGTH)
+            .and_then(|val| val.to_str().ok()?.parse().ok());
+        // manifests can be large, the timeout applies to every read of the body instead of the whole download
+        loop {
+            let chunk = match tokio::time::timeout(self.timeout, response.body_mut().data()).await {
+                Ok(Some(chunk)) => chunk?,
+                Ok(None) => break,
+                Err(_) => return Err(ClientError::Timeout)
+            };
+            result.extend_from_slice(&chunk);
+            progress(result.len() as u64, content_length);
+        }
+
+        Ok(result)
+    }
+
+    fn request(&self) -> hyper::http::request::Builder {
+        Request::builder().header(hyper::header::USER_AGENT, self.user_agent.as_str())
+    }
+
+    async fn send(&self, request: Request<Body>) -> ClientResult<impl Buf> {
+        // requests are rebuilt from their parts to be sent again after a 429
+        let (parts, body) = request.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+
+        let mut attempt = 0;
+        loop {
+            let deadline = tokio::time::Instant::now() + self.timeout;
+            let response = match tokio::time::timeout_at(deadline, self.http.request(rebuild_request(&parts, &body))).await {
+                Ok(response) => response?,
+                Err(_) => return Err(ClientError::Timeout)
+            };
+
+            if response.status() == StatusCode::TOO_MANY_REQUESTS {
+                let retry_after = retry_after(&response);
+                let delay = retry_after.unwrap_or(std::time::Duration::from_secs(DEFAULT_RETRY_AFTER_SECONDS));
+                if attempt >= MAX_RATE_LIMIT_RETRIES || delay.as_secs() > MAX_RETRY_AFTER_SECONDS {
+                    return Err(ClientError::RateLimited(retry_after));
+                }
+
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+                continue;
+            }
+
+            return match tokio::time::timeout_at(deadline, Self::process_response(response)).await {
+                Ok(result) => result,
+                Err(_) => Err(ClientError::Timeout)
+            };
+        }
+    }
+
+    async fn process_response(res: Response<Body>) -> ClientResult<impl Buf> {
         let is_success = res.status().is_success();
         let data = hyper::body::aggregate(res).await?;
         if !is_success { 
             let error: EpicError = serde_json::from_reader(data.reader())?;
-            return Err(Box::new(error))
+            return Err(ClientError::Api(error))
         }
 
         Ok(data)
     }
 
+    /// Kills the session of the current access token on Epic's side and forgets the authentication.
+    /// Does nothing if the client isn't authenticated.
+    /// 
+    /// # Errors
+    /// 
+    /// Will return `Err` if the request was not successful, the authentication is kept in that case
+    pub async fn logout(&mut self) -> ClientResult<()> {
+        let access_token = match &self.auth {
+            Some(auth) => &auth.access_token,
+            None => return Ok(())
+        };
+
+        let request = self.request()
+            .method(Method::DELETE)
+            .uri(format!("{}/account/api/oauth/sessions/kill/{}", ACCOUNT_PUBLIC_SERVICE, access_token))
+            .header("Authorization", self.get_authentication_header())
+            .body(Body::empty())?;
+
+        self.send(request).await?;
+        self.auth = None;
+        self.client_token = None;
+        Ok(())
+    }
+
     pub fn set_authentication(&mut self, auth: AuthResponse) {
         self.auth = Some(auth);
     }
 
-    // todo: check for expiration
-    fn requires_authentication(&self) ->Result<()> {
+    /// Sets the client token used by `set_auto_refresh`, for an authentication that wasn't made by this
+    /// client, e.g. one restored with `load_auth`.
+    pub fn set_client_token(&mut self, client_token: ClientToken) {
+        self.client_token = Some(client_token);
+    }
+
+    /// When enabled, authenticated requests refresh an expired access token once
+    /// using the client token of the last successful authentication.
+    pub fn set_auto_refresh(&mut self, auto_refresh: bool) {
+        self.auto_refresh = auto_refresh;
+    }
+
+    /// Sets the timeout applied to every request made by the client, 30 seconds by default. Manifest downloads
+    /// only time out when the body stops arriving for that long, not when the whole download takes longer.
+    pub fn set_timeout(&mut self, timeout: std::time::Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Sets the User-Agent sent with every request, `epic_manifest_parser/<version>` by default.
+    /// Some endpoints only accept the User-Agent of the official launcher.
+    pub fn set_user_agent(&mut self, user_agent: &str) {
+        self.user_agent = user_agent.to_owned();
+    }
+
+    /// Sends every request through the proxy at `uri` instead of the ones set in the
+    /// `http_proxy` and `https_proxy` environment variables. The other settings of the
+    /// http service are kept.
+    /// 
+    /// # Errors
+    /// 
+    /// Will return `Err` if `uri` is not a valid uri, or if the http service is shared, e.g. passed to
+    /// `with_http_service` or handed out by `http_service`, as the proxy would only change for this client
+    pub fn set_proxy(&mut self, uri: &str) -> ClientResult<()> {
+        match Arc::get_mut(&mut self.http) {
+            Some(http) => *http = http.clone().with_proxy(uri)?,
+            None => return Err(ClientError::Other(Box::new(ParserError::new("can't change the proxy of a shared http service, set it on the service instead"))))
+        }
+
+        Ok(())
+    }
+
+    /// The service whose connection pool the client sends its requests over.
+    pub fn http_service(&self) -> Arc<HttpService> {
+        self.http.clone()
+    }
+
+    async fn ensure_authentication(&mut self) -> ClientResult<()> {
+        if self.auto_refresh && self.is_token_expired() {
+            if let Some(client_token) = self.client_token.clone() {
+                self.refresh_authentication(&client_token).await?;
+            }
+        }
+
+        self.requires_authentication()
+    }
+
+    /// The account the client is authenticated as, `None` if it isn't or the grant has no account.
+    pub fn account_id(&self) -> Option<&str> {
+        self.auth.as_ref()?.account_id.as_deref()
+    }
+
+    pub fn display_name(&self) -> Option<&str> {
+        self.auth.as_ref()?.display_name.as_deref()
+    }
+
+    /// The bearer token of the current authentication, for calls to other Epic services.
+    pub fn access_token(&self) -> Option<&str> {
+        self.auth.as_ref().map(|auth| auth.access_token.as_str())
+    }
+
+    /// Returns `true` if the access token expires within the grace window or its
+    /// expiration can't be parsed. Returns `false` if the client is not authenticated.
+    pub fn is_token_expired(&self) -> bool {
+        match &self.auth {
+            Some(auth) => is_auth_expired(auth),
+            None => false
+        }
+    }
+
+    /// Writes the access and refresh token to `path`, only readable by the current user on unix.
+    /// 
+    /// # Errors
+    /// 
+    /// Will return `Err` if the client is not authenticated or the file could not be written
+    pub fn save_auth(&self, path: &Path) -> ClientResult<()> {
+        let auth = match &self.auth {
+            Some(auth) => auth,
+            None => return Err(ClientError::NotAuthenticated)
+        };
+
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut file = options.open(path)?;
+        // the mode only applies to new files, an existing one may still be readable by others
+        #[cfg(unix)]
+        file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+        file.write_all(&serde_json::to_vec(auth)?)?;
+        Ok(())
+    }
+
+    /// Restores the authentication written by `save_auth`. The client token isn't stored with it,
+    /// call `set_client_token` for `set_auto_refresh` to be able to refresh the restored session.
+    /// 
+    /// # Errors
+    /// 
+    /// Will return `Err` if the file could not be read or parsed, or if the stored access token has expired
+    pub fn load_auth(&mut self, path: &Path) -> ClientResult<()> {
+        let auth: AuthResponse = serde_json::from_slice(&std::fs::read(path)?)?;
+        if is_auth_expired(&auth) {
+            return Err(ClientError::TokenExpired);
+        }
+
+        self.set_authentication(auth);
+        Ok(())
+    }
+
+    fn requires_authentication(&self) -> ClientResult<()> {
         if self.auth.is_none() {
-            return Err(Box::new(ParserError::new("the client must be authenticated.")));
+            return Err(ClientError::NotAuthenticated);
+        }
+
+        if self.is_token_expired() {
+            return Err(ClientError::TokenExpired);
         }
 
         Ok(())
@@ -232,8 +725,137 @@ impl EpicGamesClient {
 
 }
 
+fn rebuild_request(parts: &hyper::http::request::Parts, body: &hyper::body::Bytes) -> Request<Body> {
+    let mut request = Request::new(Body::from(body.clone()));
+    *request.method_mut() = parts.method.clone();
+    *request.uri_mut() = parts.uri.clone();
+    *request.version_mut() = parts.version;
+    *request.headers_mut() = parts.headers.clone();
+    request
+}
+
+// Retry-After is either a number of seconds or an HTTP date
+fn retry_after(response: &Response<Body>) -> Option<std::time::Duration> {
+    let value = response.headers().get(hyper::header::RETRY_AFTER)?.to_str().ok()?;
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(std::time::Duration::from_secs(seconds));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    Some((date.with_timezone(&Utc) - Utc::now()).to_std().unwrap_or_default())
+}
+
+fn is_auth_expired(auth: &AuthResponse) -> bool {
+    match auth.expires_at() {
+        Ok(expires_at) => Utc::now() + Duration::seconds(TOKEN_EXPIRATION_GRACE_SECONDS) >= expires_at,
+        Err(_) => true
+    }
+}
+
 impl Default for EpicGamesClient {
     fn default() -> Self {
         Self::new()
     }
+}
+
+/// Configures an `EpicGamesClient`, every option starts out like `EpicGamesClient::new`.
+pub struct EpicGamesClientBuilder {
+    http_service: Option<Arc<HttpService>>,
+    proxy: Option<String>,
+    auto_refresh: bool,
+    timeout: std::time::Duration,
+    user_agent: String
+}
+
+impl EpicGamesClientBuilder {
+
+    pub fn new() -> Self {
+        Self {
+            http_service: None,
+            proxy: None,
+            auto_refresh: false,
+            timeout: http::DEFAULT_REQUEST_TIMEOUT,
+            user_agent: http::DEFAULT_USER_AGENT.to_owned()
+        }
+    }
+
+    /// Sends requests over the connection pool of `http_service`, see `EpicGamesClient::with_http_service`.
+    /// The proxy of the builder is ignored in favor of the one of the service.
+    pub fn set_http_service(&mut self, http_service: Arc<HttpService>) -> &mut Self {
+        self.http_service = Some(http_service);
+        self
+    }
+
+    /// See `EpicGamesClient::set_proxy`.
+    pub fn set_proxy(&mut self, uri: &str) -> &mut Self {
+        self.proxy = Some(uri.to_owned());
+        self
+    }
+
+    /// See `EpicGamesClient::set_auto_refresh`.
+    pub fn set_auto_refresh(&mut self, auto_refresh: bool) -> &mut Self {
+        self.auto_refresh = auto_refresh;
+        self
+    }
+
+    /// See `EpicGamesClient::set_timeout`.
+    pub fn set_timeout(&mut self, timeout: std::time::Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// See `EpicGamesClient::set_user_agent`.
+    pub fn set_user_agent(&mut self, user_agent: &str) -> &mut Self {
+        self.user_agent = user_agent.to_owned();
+        self
+    }
+
+    /// # Errors
+    /// 
+    /// Will return `Err` if the proxy is not a valid uri
+    pub fn build(&self) -> ClientResult<EpicGamesClient> {
+        let http = match (&self.http_service, &self.proxy) {
+            (Some(http_service), _) => http_service.clone(),
+            (None, Some(proxy)) => Arc::new(HttpService::new().with_proxy(proxy)?),
+            (None, None) => Arc::new(HttpService::new())
+        };
+
+        let mut client = EpicGamesClient::with_http_service(http);
+        client.set_auto_refresh(self.auto_refresh);
+        client.set_timeout(self.timeout);
+        client.set_user_agent(&self.user_agent);
+        Ok(client)
+    }
+
+}
+
+impl Default for EpicGamesClientBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_proxy_keeps_the_service_settings() {
+        let http = HttpService::new().with_user_agent("test-agent");
+        let mut client = EpicGamesClient::with_http_service(Arc::new(http));
+
+        client.set_proxy("http://127.0.0.1:8080").unwrap();
+        assert!(format!("{:?}", client.http_service()).contains("test-agent"));
+        assert!(client.set_proxy("not a uri").is_err());
+    }
+
+    #[test]
+    fn set_proxy_rejects_a_shared_service() {
+        let mut client = EpicGamesClient::new();
+        let shared = client.http_service();
+
+        assert!(client.set_proxy("http://127.0.0.1:8080").is_err());
+        drop(shared);
+        assert!(client.set_proxy("http://127.0.0.1:8080").is_ok());
+    }
 }
\ No newline at end of file
@@ -1,22 +1,32 @@
 #![allow(dead_code)]
 
 use string_builder::Builder as StringBuilder;
+use serde::{Serialize, Serializer};
 use serde_json::Value;
-use miniz_oxide::inflate::decompress_to_vec_zlib;
+use futures::stream::{self, TryStreamExt};
+use miniz_oxide::inflate::decompress_to_vec_zlib_with_limit;
+use aes::Aes256;
+use aes::cipher::{BlockDecrypt, KeyInit, generic_array::GenericArray};
 
-use byteorder::ReadBytesExt;
-use thoo_readext::ReadExt;
+use byteorder::{LittleEndian, ReadBytesExt};
 use http::Uri;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::Display;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 use std::io::{Cursor, SeekFrom, Seek, Read};
+use std::path::{Path, PathBuf};
 
-use crate::chunk::{FileManifest, FileManifestBuilder, FileChunk, FileChunkPart, ManifestContext};
-use crate::{Result, http::HttpService};
+use crate::cache::ChunkCache;
+use crate::chunk::{DownloadStats, FileManifest, FileManifestBuilder, FileChunk, FileChunkPart, ManifestContext};
+use crate::{Result, ParserError, hash, http::{HttpService, DEFAULT_REQUEST_TIMEOUT, DEFAULT_USER_AGENT}};
 
 const MANIFEST_HEADER_MAGIC: u32 = 0x44BEC00C;
+// Magic, header size, both data sizes, SHA hash, storage flags and version
+const MANIFEST_HEADER_MIN_SIZE: usize = 41;
 
 const EMANIFEST_STORAGE_FLAG_COMPRESSED: u8 = 0x01;
 const EMANIFEST_STORAGE_FLAG_ENCRYPTED: u8 = 0x02;
@@ -25,10 +35,28 @@ const EMANIFEST_META_VERSION_ORIGINAL: u8 = 0;
 const EMANIFEST_META_VERSION_SERIALIZES_BUILD_ID: u8 = 1;
 const EMANIFEST_META_VERSION_LATEST: u8 = EMANIFEST_META_VERSION_SERIALIZES_BUILD_ID + 1;
 
+const EFEATURE_LEVEL_DATA_FILE_RENAMES: i32 = 3;
+const EFEATURE_LEVEL_CHUNK_COMPRESSION_SUPPORT: i32 = 6;
+const EFEATURE_LEVEL_STORES_CHUNK_FILE_SIZES: i32 = 8;
+const EFEATURE_LEVEL_VARIABLE_SIZE_CHUNKS_WITHOUT_WINDOW_SIZE_CHUNK_INFO: i32 = 15;
+const EFEATURE_LEVEL_USES_BUILD_TIME_GENERATED_BUILD_ID: i32 = 18;
+const EFEATURE_LEVEL_LATEST: i32 = EFEATURE_LEVEL_USES_BUILD_TIME_GENERATED_BUILD_ID;
+// Written to JSON manifests by some engine versions instead of the real level
+const EFEATURE_LEVEL_BROKEN_JSON_VERSION: i32 = 255;
+
+const DEFAULT_MAX_CONCURRENT_DOWNLOADS: usize = 16;
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const DEFAULT_INSTALL_CONCURRENCY: usize = 4;
+const DEFAULT_CHUNK_MEMORY_CACHE_SIZE: usize = 64 * 1024 * 1024;
+
+// Counts and lengths come from the manifest, a corrupted one must not make us
+// reserve gigabytes before the data behind them turns out to be missing
+const MAX_PREALLOCATED_ELEMENTS: usize = 4096;
+
 type ByteCursor = Cursor<Vec<u8>>;
 
 // todo: move to other file
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct FGuid {
     pub a: u32,
     pub b: u32,
@@ -42,10 +70,10 @@ impl FGuid {
     where 
         R: ReadBytesExt 
     {
-        let a = reader.read_u32_le()?;
-        let b = reader.read_u32_le()?;
-        let c = reader.read_u32_le()?;
-        let d = reader.read_u32_le()?;
+        let a = reader.read_u32::<LittleEndian>()?;
+        let b = reader.read_u32::<LittleEndian>()?;
+        let c = reader.read_u32::<LittleEndian>()?;
+        let d = reader.read_u32::<LittleEndian>()?;
 
         Ok(Self {
             a,
@@ -63,6 +91,36 @@ impl Display for FGuid {
     }
 }
 
+// GUIDs are parsed the same way `FGuid` displays them: four big endian hex groups
+impl FromStr for FGuid {
+    type Err = ParserError;
+
+    fn from_str(hex: &str) -> std::result::Result<Self, Self::Err> {
+        if hex.len() != 32 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ParserError::new(&format!("invalid guid: {}", hex)));
+        }
+
+        let group = |range: std::ops::Range<usize>| u32::from_str_radix(&hex[range], 16)
+            .map_err(|_| ParserError::new(&format!("invalid guid: {}", hex)));
+
+        Ok(Self {
+            a: group(0..8)?,
+            b: group(8..16)?,
+            c: group(16..24)?,
+            d: group(24..32)?
+        })
+    }
+}
+
+impl Serialize for FGuid {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer
+    {
+        serializer.collect_str(self)
+    }
+}
+
 #[derive(Debug)]
 pub struct ChunkSha {
     pub data: [u8; 20]
@@ -78,6 +136,31 @@ impl ChunkSha {
             data
         })
     }
+
+    /// Whether `data` hashes to this sha.
+    pub fn matches(&self, data: &[u8]) -> bool {
+        hash::sha1(data) == self.data
+    }
+}
+
+impl Display for ChunkSha {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", hex::encode_upper(self.data))
+    }
+}
+
+impl FromStr for ChunkSha {
+    type Err = ParserError;
+
+    fn from_str(hex: &str) -> std::result::Result<Self, Self::Err> {
+        let mut data = [0u8; 20];
+        hex::decode_to_slice(hex, &mut data)
+            .map_err(|_| ParserError::new(&format!("invalid chunk sha: {}", hex)))?;
+
+        Ok(Self {
+            data
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -87,22 +170,74 @@ pub struct ManifestInfo {
     pub build_version: String,
     pub hash: String,
     pub file_name: String,
-    pub uri: String
+    pub uri: String,
+    pub uris: Vec<String>
 }
 
 impl ManifestInfo {
 
+    /// Parses the first element of a manifest info response.
     pub fn new(json: &Value) -> Result<Self> {
-        let root_element = json.get("elements").unwrap().as_array().unwrap().first().unwrap();
-        let app_name = root_element.get("appName").unwrap().as_str().unwrap();
-        let label_name = root_element.get("labelName").unwrap().as_str().unwrap();
-        let build_version = root_element.get("buildVersion").unwrap().as_str().unwrap();
-        let hash = root_element.get("hash").unwrap().as_str().unwrap();
+        match json_array(json, "elements")?.first() {
+            Some(element) => Self::from_element(element),
+            None => Err(Box::new(ParserError::new("manifest info contains no elements")))
+        }
+    }
+
+    /// Parses every element of a manifest info response, e.g. one for each platform.
+    pub fn all(json: &Value) -> Result<Vec<Self>> {
+        json_array(json, "elements")?.iter().map(Self::from_element).collect()
+    }
+
+    /// Checks `data` against the SHA1 of the manifest file, skipped when `hash` is empty.
+    /// 
+    /// # Errors
+    /// 
+    /// Will return `Err` if the hash doesn't match
+    pub fn verify(&self, data: &[u8]) -> Result<()> {
+        if self.hash.is_empty() {
+            return Ok(());
+        }
+
+        let sha = hash::sha1(data);
+        if !hash::matches_hex(&sha, &self.hash) {
+            return Err(Box::new(ParserError::new(&format!("manifest hash mismatch for {}: expected {}, got {}", self.file_name, self.hash, hash::to_hex(&sha)))));
+        }
+
+        Ok(())
+    }
+
+    /// The query parameters of the manifest uri, `None` if it has none.
+    pub fn query(&self) -> Option<&str> {
+        self.uri.split_once('?').map(|(_, query)| query)
+    }
+
+    /// Name of the manifest in a cache directory, `file_name` prefixed with the hash of the manifest,
+    /// or its build version when there is no hash, so a new build under the same file name is
+    /// never served from the cache.
+    pub fn cache_file_name(&self) -> String {
+        let version = if self.hash.is_empty() { &self.build_version } else { &self.hash };
+        if version.is_empty() {
+            return self.file_name.clone();
+        }
+
+        // build versions look like ++Fortnite+Release-20.00-CL-19458861-Windows
+        let version: String = version.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || "+-._".contains(c) { c.to_ascii_lowercase() } else { '_' })
+            .collect();
+        format!("{}_{}", version, self.file_name)
+    }
+
+    fn from_element(root_element: &Value) -> Result<Self> {
+        let app_name = json_str(root_element, "appName")?;
+        let label_name = json_str(root_element, "labelName")?;
+        let build_version = json_str(root_element, "buildVersion")?;
+        let hash = json_str(root_element, "hash")?;
 
         let mut uris: Vec<String> = vec![];
-        let manifests = root_element.get("manifests").unwrap().as_array().unwrap();
+        let manifests = json_array(root_element, "manifests")?;
         for manifest in manifests {
-            let uri = manifest.get("uri").unwrap().as_str().unwrap();
+            let uri = json_str(manifest, "uri")?;
             let mut uri_builder = StringBuilder::default();
             uri_builder.append(uri);
 
@@ -110,8 +245,8 @@ impl ManifestInfo {
                 if let Some(query_params) = query_params_value.as_array() {
                     let mut first_query = true;
                     for param_value in query_params {
-                        let name = param_value.get("name").unwrap().as_str().unwrap();
-                        let value = param_value.get("value").unwrap().as_str().unwrap();
+                        let name = json_str(param_value, "name")?;
+                        let value = json_str(param_value, "value")?;
 
                         let param = format!("{}={}", name, value);
                         if first_query {
@@ -127,11 +262,16 @@ impl ManifestInfo {
             uris.push(uri_builder.string()?);
         }
 
-        let uri_str = uris.first().unwrap();
+        let uri_str = match uris.first() {
+            Some(uri) => uri,
+            None => return Err(Box::new(ParserError::new("manifest info contains no manifests")))
+        };
         let uri: Uri = uri_str.parse()?;
-        let path = uri.path();
-        let from = path.chars().count() - path.chars().rev().position(|c| c == '/').unwrap();
-        let file_name = &path[from..];
+        // the last segment of the path, skipping a trailing slash
+        let file_name = match uri.path().rsplit('/').find(|segment| !segment.is_empty()) {
+            Some(file_name) => file_name,
+            None => return Err(Box::new(ParserError::new(&format!("manifest uri {} has no file name", uri_str))))
+        };
 
         Ok(Self {
             app_name: app_name.to_owned(),
@@ -139,7 +279,8 @@ impl ManifestInfo {
             build_version: build_version.to_owned(),
             hash: hash.to_owned(),
             file_name: file_name.to_owned(),
-            uri: uri_str.clone()
+            uri: uri_str.clone(),
+            uris
          })
     }
 
@@ -148,19 +289,305 @@ impl ManifestInfo {
 #[derive(Debug)]
 pub struct ManifestOptions {
     pub cache_directory: Option<String>,
-    pub chunk_base_uri: String
+    pub chunk_base_uris: Vec<String>,
+    pub decryption_key: Option<[u8; 32]>,
+    pub verify_chunks: bool,
+    pub verify_manifest: bool,
+    pub offline: bool,
+    pub max_concurrent_downloads: usize,
+    pub max_retries: u32,
+    pub request_timeout: Duration,
+    pub shard_cache: bool,
+    pub max_bytes_per_second: Option<u64>,
+    pub chunk_memory_cache_size: usize,
+    pub user_agent: String,
+    pub proxy: Option<String>,
+    pub http_service: Option<Arc<HttpService>>,
+    pub chunk_cache: Option<Arc<dyn ChunkCache>>,
+    pub chunk_query: Option<String>
 }
 
 impl ManifestOptions {
-    pub fn new(chunk_base_uri: &str, cache_directory: Option<String>) -> Self {
+    /// Without a `chunk_base_uri`, chunks are downloaded from the manifest's `BaseUrls`, falling back
+    /// to the next one when a chunk fails. A `file://` uri, e.g. `file:///mnt/mirror/ChunksV4/`, reads
+    /// the chunks from a mirror on disk instead.
+    pub fn new(chunk_base_uri: Option<&str>, cache_directory: Option<String>) -> Self {
         Self {
             cache_directory,
-            chunk_base_uri: chunk_base_uri.to_owned()
+            chunk_base_uris: chunk_base_uri.map(str::to_owned).into_iter().collect(),
+            decryption_key: None,
+            verify_chunks: true,
+            verify_manifest: true,
+            offline: false,
+            max_concurrent_downloads: DEFAULT_MAX_CONCURRENT_DOWNLOADS,
+            max_retries: DEFAULT_MAX_RETRIES,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            shard_cache: false,
+            max_bytes_per_second: None,
+            chunk_memory_cache_size: DEFAULT_CHUNK_MEMORY_CACHE_SIZE,
+            user_agent: DEFAULT_USER_AGENT.to_owned(),
+            proxy: None,
+            http_service: None,
+            chunk_cache: None,
+            chunk_query: None
+        }
+    }
+
+    /// Downloads chunks from the first of `chunk_base_uris`, trying the next one when a chunk fails. A host
+    /// that can't be reached, responds with a client error or times out is left right away, other errors
+    /// are retried `max_retries` times first. Hosts that failed are skipped by the later downloads.
+    /// Replaces the `BaseUrls` of the manifest.
+    pub fn set_chunk_base_uris(&mut self, chunk_base_uris: &[&str]) -> &mut Self {
+        self.chunk_base_uris = chunk_base_uris.iter().map(|uri| (*uri).to_owned()).collect();
+        self
+    }
+
+    /// Sets the AES-256 key used to decrypt manifests stored with the encrypted flag.
+    pub fn set_decryption_key(&mut self, key: [u8; 32]) -> &mut Self {
+        self.decryption_key = Some(key);
+        self
+    }
+
+    /// Enables SHA1 verification of downloaded chunks, on by default.
+    pub fn set_verify_chunks(&mut self, verify_chunks: bool) -> &mut Self {
+        self.verify_chunks = verify_chunks;
+        self
+    }
+
+    /// Enables checking the manifest data against the SHA1 stored in its header, on by default.
+    pub fn set_verify_manifest(&mut self, verify_manifest: bool) -> &mut Self {
+        self.verify_manifest = verify_manifest;
+        self
+    }
+
+    /// Only loads chunks from the cache and fails with the missing chunk instead of
+    /// downloading it, off by default.
+    pub fn set_offline(&mut self, offline: bool) -> &mut Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Limits how many chunks are downloaded at the same time, 16 by default.
+    pub fn set_max_concurrent_downloads(&mut self, max_concurrent_downloads: usize) -> &mut Self {
+        self.max_concurrent_downloads = max_concurrent_downloads;
+        self
+    }
+
+    /// Sets how many times a failed chunk request is retried before giving up, 3 by default.
+    pub fn set_max_retries(&mut self, max_retries: u32) -> &mut Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets the timeout of a single chunk request, 30 seconds by default. It covers receiving the response
+    /// headers and every read of the body, a large chunk may take longer in total.
+    pub fn set_request_timeout(&mut self, request_timeout: Duration) -> &mut Self {
+        self.request_timeout = request_timeout;
+        self
+    }
+
+    /// Stores cached chunks in subdirectories named after the first two characters of their hash,
+    /// off by default to keep existing flat caches working.
+    pub fn set_shard_cache(&mut self, shard_cache: bool) -> &mut Self {
+        self.shard_cache = shard_cache;
+        self
+    }
+
+    /// Limits the combined download speed of all chunk downloads, unlimited by default.
+    pub fn set_max_bytes_per_second(&mut self, max_bytes_per_second: u64) -> &mut Self {
+        self.max_bytes_per_second = Some(max_bytes_per_second);
+        self
+    }
+
+    /// Limits the combined size of the decompressed chunks kept in memory, so chunks referenced by
+    /// several parts are only downloaded and decompressed once, 64 MiB by default. 0 disables it.
+    pub fn set_chunk_memory_cache_size(&mut self, chunk_memory_cache_size: usize) -> &mut Self {
+        self.chunk_memory_cache_size = chunk_memory_cache_size;
+        self
+    }
+
+    /// Sets the User-Agent sent with chunk requests, `epic_manifest_parser/<version>` by default.
+    pub fn set_user_agent(&mut self, user_agent: &str) -> &mut Self {
+        self.user_agent = user_agent.to_owned();
+        self
+    }
+
+    /// Downloads chunks through the proxy at `proxy`, by default the `http_proxy` and `https_proxy`
+    /// environment variables are used.
+    pub fn set_proxy(&mut self, proxy: &str) -> &mut Self {
+        self.proxy = Some(proxy.to_owned());
+        self
+    }
+
+    /// Downloads chunks with `http_service`, sharing its connections with other users of the service
+    /// such as `EpicGamesClient::http_service`. The request timeout, User-Agent and proxy of the
+    /// options are ignored in favor of the ones of the service.
+    pub fn set_http_service(&mut self, http_service: Arc<HttpService>) -> &mut Self {
+        self.http_service = Some(http_service);
+        self
+    }
+
+    /// Stores downloaded chunks in `chunk_cache` instead of the cache directory, which is ignored
+    /// together with `shard_cache` then.
+    pub fn set_chunk_cache(&mut self, chunk_cache: Arc<dyn ChunkCache>) -> &mut Self {
+        self.chunk_cache = Some(chunk_cache);
+        self
+    }
+
+    /// Appends `query`, e.g. `Policy=...&Signature=...`, to every chunk uri for CDNs that only serve
+    /// signed requests. `ManifestInfo::query` returns the query of the manifest uri, which usually
+    /// signs the chunks as well.
+    pub fn set_chunk_query(&mut self, query: &str) -> &mut Self {
+        self.chunk_query = Some(query.trim_start_matches('?').to_owned());
+        self
+    }
+}
+
+/// A file of an installation that doesn't match the manifest, see `Manifest::verify_install`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyIssue {
+    Missing { file: String },
+    SizeMismatch { file: String, expected: u64, actual: u64 },
+    HashMismatch { file: String, expected: String, actual: String }
+}
+
+impl VerifyIssue {
+    /// Name of the affected file in the manifest.
+    pub fn file(&self) -> &str {
+        match self {
+            VerifyIssue::Missing { file } |
+            VerifyIssue::SizeMismatch { file, .. } |
+            VerifyIssue::HashMismatch { file, .. } => file
         }
     }
 }
 
 #[derive(Debug)]
+pub struct InstallOptions {
+    pub install_tags: Option<Vec<String>>,
+    pub concurrency: usize,
+    pub skip_valid_files: bool
+}
+
+impl InstallOptions {
+    /// Installs every file of the manifest, 4 at a time, skipping files that are already installed.
+    pub fn new() -> Self {
+        Self {
+            install_tags: None,
+            concurrency: DEFAULT_INSTALL_CONCURRENCY,
+            skip_valid_files: true
+        }
+    }
+
+    /// Only installs the untagged files and the files tagged with one of `install_tags`.
+    pub fn set_install_tags(&mut self, install_tags: Vec<String>) -> &mut Self {
+        self.install_tags = Some(install_tags);
+        self
+    }
+
+    /// Limits how many files are downloaded at the same time, 4 by default.
+    pub fn set_concurrency(&mut self, concurrency: usize) -> &mut Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    /// Keeps files whose size and SHA1 already match the manifest instead of downloading them again, on by default.
+    pub fn set_skip_valid_files(&mut self, skip_valid_files: bool) -> &mut Self {
+        self.skip_valid_files = skip_valid_files;
+        self
+    }
+}
+
+impl Default for InstallOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The metadata at the start of a manifest, see [`Manifest::read_metadata`].
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ManifestMeta {
+    pub feature_level: i32,
+    pub is_file_data: bool,
+    pub app_id: i32,
+    pub app_name: String,
+    pub build_version: String,
+    pub launch_exe: String,
+    pub launch_command: String,
+    pub prereq_ids: Vec<String>,
+    pub prereq_name: String,
+    pub prereq_path: String,
+    pub prereq_args: String,
+    pub build_id: String
+}
+
+impl ManifestMeta {
+
+    fn read<R>(reader: &mut R) -> Result<Self>
+    where
+        R: Read + Seek
+    {
+        let mut meta = Self::default();
+
+        let start_pos = reader.stream_position()?;
+        let data_size = reader.read_i32::<LittleEndian>()?;
+        let data_version = reader.read_u8()?;
+        if data_version >= EMANIFEST_META_VERSION_ORIGINAL {
+            meta.feature_level = reader.read_i32::<LittleEndian>()?;
+            check_feature_level(meta.feature_level)?;
+            meta.is_file_data = reader.read_u8()? != 0x00;
+            meta.app_id = reader.read_i32::<LittleEndian>()?;
+            meta.app_name = read_fstring(reader)?;
+            meta.build_version = read_fstring(reader)?;
+            meta.launch_exe = read_fstring(reader)?;
+            meta.launch_command = read_fstring(reader)?;
+            meta.prereq_ids = read_tarray(reader, read_fstring)?;
+            meta.prereq_name = read_fstring(reader)?;
+            meta.prereq_path = read_fstring(reader)?;
+            meta.prereq_args = read_fstring(reader)?;
+        }
+
+        if data_version >= EMANIFEST_META_VERSION_SERIALIZES_BUILD_ID {
+            meta.build_id = read_fstring(reader)?;
+        }
+
+        seek_past_section(reader, start_pos, data_size)?;
+        Ok(meta)
+    }
+
+    fn from_json(json: &Value) -> Result<Self> {
+        let feature_level = match json_str(json, "ManifestFileVersion") {
+            Ok(blob) => match i32::try_from(blob_to_u32(blob)?)? {
+                EFEATURE_LEVEL_BROKEN_JSON_VERSION => EFEATURE_LEVEL_STORES_CHUNK_FILE_SIZES,
+                level => level
+            },
+            Err(_) => 0
+        };
+        check_feature_level(feature_level)?;
+
+        Ok(Self {
+            feature_level,
+            is_file_data: json.get("bIsFileData").and_then(Value::as_bool).unwrap_or_default(),
+            app_id: i32::try_from(blob_to_u32(json_str(json, "AppID")?)?)?,
+            app_name: json_str(json, "AppNameString")?.to_owned(),
+            build_version: json_str(json, "BuildVersionString")?.to_owned(),
+            launch_exe: json_str(json, "LaunchExeString")?.to_owned(),
+            launch_command: json_str(json, "LaunchCommand")?.to_owned(),
+            prereq_ids: match json.get("PrereqIds").and_then(Value::as_array) {
+                Some(ids) => ids.iter().filter_map(Value::as_str).map(str::to_owned).collect(),
+                None => vec![]
+            },
+            prereq_name: json_str(json, "PrereqName").unwrap_or_default().to_owned(),
+            prereq_path: json_str(json, "PrereqPath").unwrap_or_default().to_owned(),
+            prereq_args: json_str(json, "PrereqArgs").unwrap_or_default().to_owned(),
+            // JSON manifests predate build ids
+            build_id: String::new()
+        })
+    }
+
+}
+
+#[derive(Debug, Serialize)]
 pub struct Manifest {
     pub app_id: i32,
     pub app_name: String,
@@ -172,220 +599,252 @@ pub struct Manifest {
     pub prereq_path: String,
     pub prereq_args: String,
     pub build_id: String,
+    /// Version of the manifest format, newer ones than the parser understands are rejected.
+    pub feature_level: i32,
+    /// Whether the files are stored whole on the CDN instead of being split into chunks.
+    pub is_file_data: bool,
+    #[serde(serialize_with = "serialize_sorted")]
     pub chunk_hashes: HashMap<FGuid, String>,
+    #[serde(serialize_with = "serialize_sorted")]
     pub chunk_shas: HashMap<FGuid, String>,
+    #[serde(serialize_with = "serialize_sorted")]
     pub data_groups: HashMap<FGuid, u8>,
+    /// Size of the chunk files on the CDN, 0 for older JSON manifests that don't list them.
+    #[serde(serialize_with = "serialize_sorted")]
     pub chunk_filesizes: HashMap<FGuid, u64>,
     pub file_manifests: Vec<FileManifest>,
+    #[serde(serialize_with = "serialize_sorted")]
     pub custom_fields: HashMap<String, String>,
+    #[serde(skip)]
     pub context: Arc<ManifestContext>
 }
 
 #[allow(dead_code)]
 impl Manifest {
     pub fn new(data: Vec<u8>, options: ManifestOptions) -> Result<Self> {
-        let mut cursor = Cursor::new(data);
-        let magic = cursor.read_u32_le()?;
-        assert!(magic == MANIFEST_HEADER_MAGIC, "JSON manifests are not supported.");
-
-        let header_size = cursor.read_i32_le()?;
-        let _data_size_uncompressed = cursor.read_i32_le()?;
-        let data_size_compressed = cursor.read_i32_le()?;
-        cursor.seek(SeekFrom::Current(20))?; // Hashes
-
-        let storage_flags = cursor.read_u8()?;
-        let _version = cursor.read_i32_le()?;
-        cursor.seek(SeekFrom::Start(u64::try_from(header_size)?))?;
-    
-        let pos = usize::try_from(cursor.position())?;
-        let data = match storage_flags {
-            EMANIFEST_STORAGE_FLAG_COMPRESSED => {
-                let compressed = &cursor.get_mut()[pos..pos+usize::try_from(data_size_compressed)?];
-                decompress_to_vec_zlib(compressed).unwrap()
-            },
-            EMANIFEST_STORAGE_FLAG_ENCRYPTED => {
-                panic!("Encrypted manifests are not supported.");
-            }
-            _ => {
-                let mut data = vec![0u8; 0];
-                let block = &cursor.get_mut()[pos..usize::try_from(data_size_compressed)?];
-                data.extend_from_slice(block);
-
-                data
-            }
-        };
-
-        let mut app_id = 0;
-        let mut app_name = String::new();
-        let mut build_version = String::new();
-        let mut launch_exe = String::new();
-        let mut launch_command = String::new();
-        let mut prereq_ids: Vec<String> = vec![];
-        let mut prereq_name = String::new();
-        let mut prereq_path = String::new();
-        let mut prereq_args = String::new();
-        let mut build_id = String::new();
-
-        let mut cursor = Cursor::new(data);
-        let start_pos = cursor.position();
-        let data_size = cursor.read_i32_le()?;
-        let data_version = cursor.read_u8()?;
-        if data_version >= EMANIFEST_META_VERSION_ORIGINAL {
-            let _feature_level = cursor.read_i32_le()?;
-            let _is_file_data = cursor.read_u8()? != 0x00;
-            app_id = cursor.read_i32_le()?;
-            app_name = cursor.read_fstring()?;
-            build_version = cursor.read_fstring()?;
-            launch_exe = cursor.read_fstring()?;
-            launch_command = cursor.read_fstring()?;
-            prereq_ids = cursor.read_array(|r| r.read_fstring().unwrap())?;
-            prereq_name = cursor.read_fstring()?;
-            prereq_path = cursor.read_fstring()?;
-            prereq_args = cursor.read_fstring()?;
+        if data.first() == Some(&b'{') {
+            return Self::from_json(&data, options);
         }
 
-        if data_version >= EMANIFEST_META_VERSION_SERIALIZES_BUILD_ID {
-            build_id = cursor.read_fstring()?;
-        }
+        let data = decode_manifest_data(&data, &options)?;
+        Self::from_payload(&mut Cursor::new(data), options)
+    }
 
-        let mut chunk_hashes: HashMap<FGuid, String> = HashMap::new();
-        let mut chunk_shas: HashMap<FGuid, String>= HashMap::new();
-        let mut data_groups: HashMap<FGuid, u8>= HashMap::new();
-        let mut chunk_filesizes: HashMap<FGuid, u64> = HashMap::new();
+    // Parses the decrypted and decompressed payload following the binary header
+    fn from_payload<R>(reader: &mut R, options: ManifestOptions) -> Result<Self>
+    where
+        R: Read + Seek
+    {
+        let ManifestMeta {
+            feature_level, is_file_data, app_id, app_name, build_version, launch_exe, launch_command,
+            prereq_ids, prereq_name, prereq_path, prereq_args, build_id
+        } = ManifestMeta::read(reader)?;
+
+        let mut chunk_rows: Vec<ChunkRow> = vec![];
 
-        cursor.seek(SeekFrom::Start(start_pos + u64::try_from(data_size)?))?;
-        let start_pos = cursor.position();      
-        let data_size = cursor.read_i32_le()?;
-        let data_version = cursor.read_u8()?;
+        let start_pos = reader.stream_position()?;
+        let data_size = reader.read_i32::<LittleEndian>()?;
+        let data_version = reader.read_u8()?;
         if data_version >= EMANIFEST_META_VERSION_ORIGINAL {
-            let count = cursor.read_i32_le()?;
-            let count_size = usize::try_from(count)?;
+            let count = reader.read_i32::<LittleEndian>()?;
 
-            let guids = cursor.read_array_with_length(|r| FGuid::new(r).unwrap(), count)?;
-        
-            chunk_hashes = HashMap::with_capacity(count_size);
-            let hash_values = cursor.read_array_with_length(|r| r.read_u64_le().unwrap(), count)?;
-            for i in 0..count {
-                let i = usize::try_from(i)?;
-                let guid = guids[i];
-                let val = hash_values[i];
-                chunk_hashes.insert(guid, format!("{:016X?}",  val));
+            // The list is stored one field after another, fill in the rows a field at a time
+            chunk_rows = read_sized_tarray(reader, count, |r| Ok(ChunkRow::new(FGuid::new(r)?)))?;
+            for row in &mut chunk_rows {
+                row.hash = reader.read_u64::<LittleEndian>()?;
             }
-
-            chunk_shas = HashMap::with_capacity(count_size);
-            let sha_offset = usize::try_from(cursor.position())?;
-            for i in 0..count {
-                let i = usize::try_from(i)?;
-                let guid = guids[i];
-                let offset = sha_offset + (i*20);
-                let data = &cursor.get_ref()[offset..offset + 20];
-                chunk_shas.insert(guid, format!("{:X?}", data));
+            for row in &mut chunk_rows {
+                reader.read_exact(&mut row.sha)?;
             }
-            cursor.seek(SeekFrom::Current((count * 20).into()))?;
-
-            data_groups = HashMap::with_capacity(count_size);
-            let group_number_offset = cursor.position();
-            let cursor_ref = cursor.get_ref();
-            for i in 0..count {
-                let i = usize::try_from(i)?;
-                let guid = guids[i];
-                data_groups.insert(guid, cursor_ref[usize::try_from(group_number_offset)? + i]);
+            for row in &mut chunk_rows {
+                row.data_group = reader.read_u8()?;
             }
-            cursor.seek(SeekFrom::Current(count.into()))?;
-            cursor.seek(SeekFrom::Current((count * 4).into()))?;
-
-            chunk_filesizes = HashMap::with_capacity(count_size);
-            let file_sizes = cursor.read_array_with_length(|r| r.read_u64_le().unwrap(), count)?;
-            for i in 0..count {
-                let i = usize::try_from(i)?;
-                let guid = guids[i];
-                let val = file_sizes[i];
-                chunk_filesizes.insert(guid, val);
+            reader.seek(SeekFrom::Current(i64::from(count) * 4))?; // WindowSize
+            for row in &mut chunk_rows {
+                row.size = reader.read_u64::<LittleEndian>()?;
             }
         }
 
         let mut file_manifests_builders: Vec<FileManifestBuilder> = vec![];
 
-        cursor.seek(SeekFrom::Start(start_pos + u64::try_from(data_size)?))?;
-        let start_pos = cursor.position();
-        let data_size = cursor.read_i32_le()?;
-        let data_version = cursor.read_u8()?;
+        seek_past_section(reader, start_pos, data_size)?;
+        let start_pos = reader.stream_position()?;
+        let data_size = reader.read_i32::<LittleEndian>()?;
+        let data_version = reader.read_u8()?;
         if data_version >= EMANIFEST_META_VERSION_ORIGINAL {
-            let count = cursor.read_i32_le()?;
+            let count = reader.read_i32::<LittleEndian>()?;
             let count_size = usize::try_from(count)?;
-            file_manifests_builders = Vec::with_capacity(count_size);
+            file_manifests_builders = Vec::with_capacity(count_size.min(MAX_PREALLOCATED_ELEMENTS));
 
             for _ in 0..count {
-                let file_name = cursor.read_fstring()?;
+                let file_name = read_fstring(reader)?;
                 file_manifests_builders.push(FileManifestBuilder::new(&file_name));
             }
 
             for _ in 0..count { // SymlinkTarget
-                let len = cursor.read_i32_le()?;
-                cursor.seek(SeekFrom::Current(i64::try_from(len)?))?;
+                let len = reader.read_i32::<LittleEndian>()?;
+                reader.seek(SeekFrom::Current(i64::from(len)))?;
             }
 
-            let sha_offset = usize::try_from(cursor.position())?;
-            for i in 0..count {
-                let i = usize::try_from(i)?;
-                let file = &mut file_manifests_builders[i];
-                let offset = sha_offset + (i*20);
-                let data = &cursor.get_ref()[offset..offset + 20];
-                file.set_hash(&format!("{:X?}", data));
+            for file in &mut file_manifests_builders {
+                let mut sha = [0u8; 20];
+                reader.read_exact(&mut sha)?;
+                file.set_hash(&hex::encode_upper(sha));
             }
-            
-            cursor.seek(SeekFrom::Current((count * 20).into()))?;
-            cursor.seek(SeekFrom::Current(count.into()))?; // FileList
+
+            reader.seek(SeekFrom::Current(count.into()))?; // FileList
 
             for file in &mut file_manifests_builders {
-                let install_tags = cursor.read_array(|r| r.read_fstring().unwrap())?;
+                let install_tags = read_tarray(reader, read_fstring)?;
                 file.set_install_tags(install_tags);
             }
 
             for file in &mut file_manifests_builders {
-                let chunk_parts = cursor.read_array(|r| FileChunkPart::new(r).unwrap())?;
+                let chunk_parts = read_tarray(reader, FileChunkPart::new)?;
                 file.set_chunk_parts(chunk_parts);
             }
         }
 
         let mut custom_fields: HashMap<String, String> = HashMap::new();
 
-        cursor.seek(SeekFrom::Start(start_pos + u64::try_from(data_size)?))?;
-        let _start_pos = cursor.position();
-        let _data_size = cursor.read_i32_le()?;
-        let data_version = cursor.read_u8()?;
-        if data_version > EMANIFEST_META_VERSION_ORIGINAL {
-            let count = cursor.read_i32_le()?;
-            custom_fields = HashMap::with_capacity(usize::try_from(count)?);
+        seek_past_section(reader, start_pos, data_size)?;
+        let _start_pos = reader.stream_position()?;
+        let _data_size = reader.read_i32::<LittleEndian>()?;
+        let data_version = reader.read_u8()?;
+        if data_version >= EMANIFEST_META_VERSION_ORIGINAL {
+            let count = reader.read_i32::<LittleEndian>()?;
+            custom_fields = HashMap::with_capacity(usize::try_from(count)?.min(MAX_PREALLOCATED_ELEMENTS));
+
+            let keys = read_sized_tarray(reader, count, read_fstring)?;
+            let values = read_sized_tarray(reader, count, read_fstring)?;
+            custom_fields.extend(keys.into_iter().zip(values));
+        }
 
-            let keys = cursor.read_array(|r| r.read_fstring().unwrap())?;
-            let values = cursor.read_array(|r| r.read_fstring().unwrap())?;
+        let (ChunkTables { chunk_hashes, chunk_shas, data_groups, chunk_filesizes, .. }, context, file_manifests) =
+            Self::build_file_manifests(options, feature_level, is_file_data, &custom_fields, chunk_rows, file_manifests_builders)?;
 
-            for i in 0..count {
-                let i = usize::try_from(i)?;
-                custom_fields.insert(keys[i].clone(), values[i].clone());
-            }
+        Ok(Self {
+            app_id,
+            app_name,
+            build_version,
+            launch_exe,
+            launch_command,
+            prereq_ids,
+            prereq_name,
+            prereq_path,
+            prereq_args,
+            build_id,
+            feature_level,
+            is_file_data,
+            chunk_hashes,
+            chunk_shas,
+            data_groups,
+            chunk_filesizes,
+            file_manifests,
+            custom_fields,
+            context
+        })
+    }
+
+    /// Parses only the metadata at the start of `data`, skipping the chunk and file tables and
+    /// everything needed to download from the manifest.
+    /// 
+    /// # Errors
+    /// 
+    /// Will return `Err` if the data isn't a valid manifest or can't be decrypted
+    pub fn read_metadata(data: &[u8], options: &ManifestOptions) -> Result<ManifestMeta> {
+        if data.first() == Some(&b'{') {
+            return ManifestMeta::from_json(&serde_json::from_slice(data)?);
         }
 
-        let mut chunks: HashMap<FGuid, FileChunk> = HashMap::with_capacity(chunk_filesizes.len());
-        for (guid, size) in &chunk_filesizes {
-            let hash = chunk_hashes.get(guid).unwrap().clone();
-            let sha = chunk_shas.get(guid).unwrap().clone();
-            let data_group = data_groups.get(guid).unwrap();
-            let chunk = FileChunk::new(*guid, *size, &hash, &sha, *data_group, &options.chunk_base_uri);
-            chunks.insert(*guid, chunk);
+        ManifestMeta::read(&mut Cursor::new(decode_manifest_data(data, options)?))
+    }
+
+    /// # Errors
+    /// 
+    /// Will return `Err` if the file couldn't be read or isn't a valid manifest
+    pub fn from_file(path: &Path, options: ManifestOptions) -> Result<Self> {
+        Self::new(std::fs::read(path)?, options)
+    }
+
+    /// # Errors
+    /// 
+    /// Will return `Err` if reading fails or the data isn't a valid manifest
+    pub fn from_reader<R: Read>(reader: &mut R, options: ManifestOptions) -> Result<Self> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        Self::new(data, options)
+    }
+
+    fn from_json(data: &[u8], options: ManifestOptions) -> Result<Self> {
+        let json: Value = serde_json::from_slice(data)?;
+
+        let ManifestMeta {
+            feature_level, is_file_data, app_id, app_name, build_version, launch_exe, launch_command,
+            prereq_ids, prereq_name, prereq_path, prereq_args, build_id
+        } = ManifestMeta::from_json(&json)?;
+
+        let chunk_hash_list = json_object(&json, "ChunkHashList")?;
+        let chunk_sha_list = json_object(&json, "ChunkShaList")?;
+        let data_group_list = json_object(&json, "DataGroupList")?;
+        // older manifests don't list the file sizes of the chunks, they are left at 0
+        let chunk_filesize_list = json.get("ChunkFilesizeList").and_then(Value::as_object);
+        let mut chunk_rows: Vec<ChunkRow> = Vec::with_capacity(chunk_hash_list.len());
+        for (guid, hash) in chunk_hash_list {
+            let missing = |table: &str| ParserError::new(&format!("missing {} for chunk {}", table, guid));
+            let mut row = ChunkRow::new(guid.parse::<FGuid>()?);
+            row.hash = blob_to_u64(json_value_str(hash, "ChunkHashList")?)?;
+            if let Some(size) = chunk_filesize_list.and_then(|list| list.get(guid)) {
+                row.size = blob_to_u64(json_value_str(size, "ChunkFilesizeList")?)?;
+            }
+            let sha = chunk_sha_list.get(guid).ok_or_else(|| missing("sha"))?;
+            hex::decode_to_slice(json_value_str(sha, "ChunkShaList")?, &mut row.sha)
+                .map_err(|_| missing("valid sha"))?;
+            let data_group = data_group_list.get(guid).ok_or_else(|| missing("data group"))?;
+            row.data_group = u8::try_from(blob_to_u64(json_value_str(data_group, "DataGroupList")?)?)?;
+            chunk_rows.push(row);
         }
 
-        let chunks = Arc::new(chunks);
-        let http = Arc::new(HttpService::new());
-        let context = Arc::new(ManifestContext::new(chunks, http, options.cache_directory));
+        let files = json.get("FileManifestList").and_then(Value::as_array)
+            .ok_or_else(|| ParserError::new("missing field: FileManifestList"))?;
+        let mut file_manifests_builders: Vec<FileManifestBuilder> = Vec::with_capacity(files.len());
+        for file in files {
+            let mut builder = FileManifestBuilder::new(json_str(file, "Filename")?);
+            let hash = blob_to_bytes(json_str(file, "FileHash")?)?;
+            builder.set_hash(&hex::encode_upper(hash));
 
-        let mut file_manifests: Vec<FileManifest> = Vec::with_capacity(file_manifests_builders.len());
-        for builder in file_manifests_builders {
-            let manifest = builder.build(context.clone());
-            file_manifests.push(manifest);
+            let install_tags = match file.get("InstallTags").and_then(Value::as_array) {
+                Some(tags) => tags.iter().filter_map(Value::as_str).map(str::to_owned).collect(),
+                None => vec![]
+            };
+            builder.set_install_tags(install_tags);
+
+            let parts = file.get("FileChunkParts").and_then(Value::as_array)
+                .ok_or_else(|| ParserError::new("missing field: FileChunkParts"))?;
+            let mut chunk_parts = Vec::with_capacity(parts.len());
+            for part in parts {
+                chunk_parts.push(FileChunkPart {
+                    guid: json_str(part, "Guid")?.parse::<FGuid>()?,
+                    offset: i32::try_from(blob_to_u32(json_str(part, "Offset")?)?)?,
+                    size: i32::try_from(blob_to_u32(json_str(part, "Size")?)?)?
+                });
+            }
+            builder.set_chunk_parts(chunk_parts);
+
+            file_manifests_builders.push(builder);
+        }
+
+        let mut custom_fields: HashMap<String, String> = HashMap::new();
+        if let Some(fields) = json.get("CustomFields").and_then(Value::as_object) {
+            for (key, value) in fields {
+                custom_fields.insert(key.clone(), json_value_str(value, key)?.to_owned());
+            }
         }
 
+        let (ChunkTables { chunk_hashes, chunk_shas, data_groups, chunk_filesizes, .. }, context, file_manifests) =
+            Self::build_file_manifests(options, feature_level, is_file_data, &custom_fields, chunk_rows, file_manifests_builders)?;
+
         Ok(Self {
             app_id,
             app_name,
@@ -397,6 +856,8 @@ impl Manifest {
             prereq_path,
             prereq_args,
             build_id,
+            feature_level,
+            is_file_data,
             chunk_hashes,
             chunk_shas,
             data_groups,
@@ -407,4 +868,932 @@ impl Manifest {
         })
     }
 
+    /// # Errors
+    /// 
+    /// Will return `Err` if the manifest couldn't be serialized
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Compares this build with `other`, treating `other` as the newer build.
+    pub fn diff<'a>(&'a self, other: &'a Manifest) -> ManifestDiff<'a> {
+        let old_files: HashMap<&str, &FileManifest> = self.file_manifests.iter().map(|f| (f.name.as_str(), f)).collect();
+        let new_files: HashMap<&str, &FileManifest> = other.file_manifests.iter().map(|f| (f.name.as_str(), f)).collect();
+
+        let mut diff = ManifestDiff::default();
+        for new in &other.file_manifests {
+            match old_files.get(new.name.as_str()) {
+                None => diff.added.push(new),
+                Some(old) if old.hash != new.hash => {
+                    let changed_parts = new.chunk_parts.iter()
+                        .filter(|part| !old.chunk_parts.iter().any(|p| p.guid == part.guid && p.offset == part.offset && p.size == part.size))
+                        .collect();
+
+                    diff.modified.push(FileDiff {
+                        old,
+                        new,
+                        changed_parts
+                    });
+                },
+                Some(_) => { }
+            }
+        }
+
+        diff.removed = self.file_manifests.iter().filter(|f| !new_files.contains_key(f.name.as_str())).collect();
+        diff.new_chunks = other.chunks_to_download(self).iter().map(|chunk| chunk.guid).collect();
+
+        diff
+    }
+
+    /// Chunks of this build that aren't part of the `installed` build, ordered by GUID.
+    /// Chunks are content addressed, so these are all an update has to download.
+    pub fn chunks_to_download(&self, installed: &Manifest) -> Vec<&FileChunk> {
+        let mut chunks: Vec<&FileChunk> = self.context.chunks.values()
+            .filter(|chunk| !installed.context.chunks.contains_key(&chunk.guid))
+            .collect();
+
+        chunks.sort_by_key(|chunk| chunk.guid);
+        chunks
+    }
+
+    /// Every chunk of the build once, in no particular order, e.g. to mirror the whole chunk store.
+    pub fn chunks(&self) -> impl Iterator<Item = &FileChunk> {
+        self.context.chunks.values()
+    }
+
+    /// Every chunk part of every file, in file order and then in the order the parts make up the file.
+    pub fn all_chunk_parts(&self) -> impl Iterator<Item = (&FileManifest, &FileChunkPart)> {
+        self.file_manifests.iter()
+            .flat_map(|file| file.chunk_parts.iter().map(move |part| (file, part)))
+    }
+
+    pub fn custom_field(&self, key: &str) -> Option<&str> {
+        self.custom_fields.get(key).map(String::as_str)
+    }
+
+    /// The chunk distribution points listed in the `BaseUrls` custom field, empty if the field is absent.
+    pub fn base_urls(&self) -> Vec<String> {
+        split_base_urls(&self.custom_fields)
+    }
+
+    /// The file named `name`, backslashes in `name` are treated as the forward slashes Epic uses.
+    pub fn file(&self, name: &str) -> Option<&FileManifest> {
+        let name = name.replace('\\', "/");
+        self.file_manifests.iter().find(|f| f.name == name)
+    }
+
+    /// Like `file`, ignoring the case of `name` as Windows paths do.
+    pub fn file_ci(&self, name: &str) -> Option<&FileManifest> {
+        let name = name.replace('\\', "/").to_lowercase();
+        self.file_manifests.iter().find(|f| f.name.to_lowercase() == name)
+    }
+
+    /// Files whose name matches `pattern`, where `*` matches any number of characters (including `/`)
+    /// and `?` a single one. Backslashes in `pattern` are treated as forward slashes.
+    pub fn files_matching(&self, pattern: &str) -> impl Iterator<Item = &FileManifest> {
+        let pattern: Vec<char> = pattern.replace('\\', "/").chars().collect();
+        self.file_manifests.iter().filter(move |f| wildcard_match(&pattern, &f.name.chars().collect::<Vec<char>>()))
+    }
+
+    /// Files tagged with `tag`, an empty tag selects the untagged files that are always installed.
+    pub fn files_with_tag<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = &'a FileManifest> {
+        self.file_manifests.iter().filter(move |f| {
+            if tag.is_empty() {
+                f.is_untagged()
+            } else {
+                f.install_tags.iter().any(|t| t == tag)
+            }
+        })
+    }
+
+    /// Files that are part of the base install, regardless of the selected tags.
+    pub fn files_without_tags(&self) -> impl Iterator<Item = &FileManifest> {
+        self.file_manifests.iter().filter(|f| f.is_untagged())
+    }
+
+    /// Bytes transferred to download `files`, chunks shared between files are only counted once.
+    pub fn download_size(&self, files: &[&FileManifest]) -> u64 {
+        let mut guids = HashSet::new();
+        files.iter()
+            .flat_map(|f| &f.chunk_parts)
+            .filter(|part| guids.insert(part.guid))
+            .filter_map(|part| self.chunk_filesizes.get(&part.guid))
+            .sum()
+    }
+
+    /// Bytes `files` take up once installed.
+    pub fn disk_size(&self, files: &[&FileManifest]) -> u64 {
+        files.iter()
+            .flat_map(|f| &f.chunk_parts)
+            .map(|part| u64::try_from(part.size).unwrap_or_default())
+            .sum()
+    }
+
+    /// Chunks loaded from the cache and downloaded by the files of this manifest so far.
+    pub fn download_stats(&self) -> DownloadStats {
+        self.context.download_stats()
+    }
+
+    /// Downloads every chunk that isn't cached yet into the cache, so files downloaded
+    /// afterwards are assembled from disk. Up to `concurrency` chunks are fetched at the same time,
+    /// still limited by `max_concurrent_downloads`.
+    /// 
+    /// # Errors
+    /// 
+    /// Will return `Err` if no cache is configured or as soon as one of the chunks fails to download
+    pub async fn prefetch_chunks(&self, concurrency: usize) -> Result<()> {
+        self.prefetch_chunks_with_progress(concurrency, |_, _| {}).await
+    }
+
+    /// Like `prefetch_chunks`, calling `progress` with the completed and total chunk count after every chunk.
+    pub async fn prefetch_chunks_with_progress<F>(&self, concurrency: usize, progress: F) -> Result<()>
+    where
+        F: Fn(usize, usize)
+    {
+        if self.context.cache.is_none() {
+            return Err(Box::new(ParserError::new("prefetching chunks requires a chunk cache")));
+        }
+
+        let missing = self.context.missing_chunks().await;
+        let total = missing.len();
+        let completed = AtomicUsize::new(0);
+        let (completed, progress) = (&completed, &progress);
+
+        stream::iter(missing.into_iter().map(Ok))
+            .try_for_each_concurrent(concurrency.max(1), |guid| async move {
+                self.context.cache_chunk(&guid).await?;
+                progress(completed.fetch_add(1, Ordering::Relaxed) + 1, total);
+                Ok(())
+            })
+            .await
+    }
+
+    /// Downloads the file named `file_name` to `output`. The data is written to a temporary
+    /// file next to `output` first, so a failed download never leaves a partial file behind.
+    /// 
+    /// # Errors
+    /// 
+    /// Will return `Err` if no file matches `file_name` or if downloading or writing the file fails
+    pub async fn download_file(&self, file_name: &str, output: &Path) -> Result<()> {
+        let file = match self.file(file_name) {
+            Some(file) => file,
+            None => return Err(Box::new(ParserError::new(&format!("no file named {} in manifest", file_name))))
+        };
+
+        Self::download_to(file, output).await
+    }
+
+    /// Downloads `files` into `output_dir`, keeping their relative paths. Up to `concurrency` files
+    /// are downloaded at the same time, the chunk downloads still share `max_concurrent_downloads`.
+    /// Configure a cache directory to reuse chunks shared between files.
+    /// 
+    /// # Errors
+    /// 
+    /// Will return `Err` if a file name would leave `output_dir` or as soon as one of the files fails to download
+    pub async fn download_files(&self, files: &[&FileManifest], output_dir: &Path, concurrency: usize) -> Result<()> {
+        stream::iter(files.iter().map(Ok))
+            .try_for_each_concurrent(concurrency.max(1), |file| async move {
+                Self::download_to(file, &install_path(output_dir, &file.name)?).await
+            })
+            .await
+    }
+
+    /// Installs the files selected by `options` into `output_dir`, recreating the directory layout
+    /// of the manifest.
+    /// 
+    /// # Errors
+    /// 
+    /// Will return `Err` if a file name would leave `output_dir` or as soon as one of the files fails to download
+    pub async fn install(&self, output_dir: &Path, options: InstallOptions) -> Result<()> {
+        self.install_with_progress(output_dir, options, |_, _| {}).await
+    }
+
+    /// Like `install`, calling `progress` with the installed and total byte count after every file.
+    pub async fn install_with_progress<F>(&self, output_dir: &Path, options: InstallOptions, progress: F) -> Result<()>
+    where
+        F: Fn(usize, usize)
+    {
+        let files: Vec<&FileManifest> = match &options.install_tags {
+            Some(tags) => self.file_manifests.iter()
+                .filter(|f| f.is_untagged() || f.install_tags.iter().any(|tag| tags.contains(tag)))
+                .collect(),
+            None => self.file_manifests.iter().collect()
+        };
+
+        let total: usize = files.iter().map(|f| f.size).sum();
+        let completed = AtomicUsize::new(0);
+        let (completed, progress, options) = (&completed, &progress, &options);
+
+        stream::iter(files.into_iter().map(Ok))
+            .try_for_each_concurrent(options.concurrency.max(1), |file| async move {
+                let path = install_path(output_dir, &file.name)?;
+                if !options.skip_valid_files || !is_installed(file, &path).await? {
+                    Self::download_to(file, &path).await?;
+                }
+
+                progress(completed.fetch_add(file.size, Ordering::Relaxed) + file.size, total);
+                Ok(())
+            })
+            .await
+    }
+
+    /// Checks the installation in `dir` against every file of the manifest, e.g. to find the files
+    /// a repair has to download again. Files are compared by size first and by SHA1 if the sizes match.
+    /// 
+    /// # Errors
+    /// 
+    /// Will return `Err` if a file name would leave `dir` or a file exists but can't be read
+    pub fn verify_install(&self, dir: &Path) -> Result<Vec<VerifyIssue>> {
+        let mut issues = Vec::new();
+        for file in &self.file_manifests {
+            let path = install_path(dir, &file.name)?;
+            let metadata = match std::fs::metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                    issues.push(VerifyIssue::Missing { file: file.name.clone() });
+                    continue;
+                },
+                Err(error) => return Err(Box::new(error))
+            };
+
+            let expected = u64::try_from(file.size)?;
+            if metadata.len() != expected {
+                issues.push(VerifyIssue::SizeMismatch { file: file.name.clone(), expected, actual: metadata.len() });
+                continue;
+            }
+
+            if file.hash.is_empty() {
+                continue;
+            }
+
+            let actual = hash::sha1_reader(&mut std::fs::File::open(&path)?)?;
+            if !hash::matches_hex(&actual, &file.hash) {
+                issues.push(VerifyIssue::HashMismatch { file: file.name.clone(), expected: file.hash.clone(), actual: hash::to_hex(&actual) });
+            }
+        }
+
+        Ok(issues)
+    }
+
+    async fn download_to(file: &FileManifest, output: &Path) -> Result<()> {
+        if let Some(parent) = output.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let mut temp_path = output.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+
+        let result = Self::write_file(file, &temp_path, output).await;
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+        }
+
+        result
+    }
+
+    async fn write_file(file: &FileManifest, temp_path: &Path, output: &Path) -> Result<()> {
+        let mut writer = tokio::fs::File::create(temp_path).await?;
+        file.save_to_async(&mut writer).await?;
+        writer.sync_all().await?;
+
+        tokio::fs::rename(temp_path, output).await?;
+        Ok(())
+    }
+
+    // Shared by the binary and the JSON format once the tables are read. The chunks end up in the context,
+    // the returned tables only hold the public lists.
+    fn build_file_manifests(options: ManifestOptions, feature_level: i32, is_file_data: bool, custom_fields: &HashMap<String, String>,
+        chunk_rows: Vec<ChunkRow>, builders: Vec<FileManifestBuilder>) -> Result<(ChunkTables, Arc<ManifestContext>, Vec<FileManifest>)> {
+        let (chunk_base_uri, mirror_base_uris) = resolve_chunk_base_uris(&options, custom_fields, feature_level, is_file_data)?;
+        let mut tables = ChunkTables::new(chunk_rows, &chunk_base_uri);
+        let mut chunks = std::mem::take(&mut tables.chunks);
+        if is_file_data {
+            chunks = file_data_table(&builders, &chunk_base_uri)?;
+        }
+        for chunk in chunks.values_mut() {
+            mirror_base_uris.iter().for_each(|base_uri| chunk.add_mirror(base_uri));
+        }
+
+        // a local mirror has no use for the query and would read it as part of the path
+        if let Some(query) = options.chunk_query.as_ref().filter(|query| !query.is_empty()) {
+            let with_query = |uri: &mut String| {
+                if !uri.starts_with("file://") {
+                    uri.push(if uri.contains('?') { '&' } else { '?' });
+                    uri.push_str(query);
+                }
+            };
+
+            for chunk in chunks.values_mut() {
+                with_query(&mut chunk.uri);
+                chunk.mirror_uris.iter_mut().for_each(with_query);
+            }
+        }
+
+        let chunks = Arc::new(chunks);
+        let http = match &options.http_service {
+            Some(http) => http.clone(),
+            None => {
+                let mut http = HttpService::with_timeout(options.request_timeout).with_user_agent(&options.user_agent);
+                if let Some(proxy) = &options.proxy {
+                    http = http.with_proxy(proxy)?;
+                }
+
+                Arc::new(http)
+            }
+        };
+        let context = Arc::new(ManifestContext::new(chunks, http, &options));
+
+        let mut file_manifests: Vec<FileManifest> = Vec::with_capacity(builders.len());
+        for builder in builders {
+            let manifest = builder.build(context.clone())?;
+            file_manifests.push(manifest);
+        }
+
+        Ok((tables, context, file_manifests))
+    }
+
+}
+
+#[derive(Debug, Default)]
+pub struct ManifestDiff<'a> {
+    pub added: Vec<&'a FileManifest>,
+    pub removed: Vec<&'a FileManifest>,
+    pub modified: Vec<FileDiff<'a>>,
+    pub new_chunks: HashSet<FGuid>
+}
+
+#[derive(Debug)]
+pub struct FileDiff<'a> {
+    pub old: &'a FileManifest,
+    pub new: &'a FileManifest,
+    /// Parts of the new file that aren't found at the same place in the old one.
+    pub changed_parts: Vec<&'a FileChunkPart>
+}
+
+// Greedy matching that backtracks to the last `*` on a mismatch
+fn wildcard_match(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            },
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            },
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    p = star + 1;
+                    t = matched + 1;
+                },
+                None => return false
+            }
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// Manifests are untrusted input, a file name must not escape the install directory
+fn install_path(output_dir: &Path, name: &str) -> Result<PathBuf> {
+    let relative = Path::new(name);
+    if !relative.components().all(|component| matches!(component, std::path::Component::Normal(_))) {
+        return Err(Box::new(ParserError::new(&format!("refusing to install {} outside of the install directory", name))));
+    }
+
+    Ok(output_dir.join(relative))
+}
+
+// Hashing a whole file blocks, so it runs on the blocking threads instead of stalling the other downloads
+async fn is_installed(file: &FileManifest, path: &Path) -> Result<bool> {
+    let (size, expected, path) = (file.size, file.hash.clone(), path.to_owned());
+    let installed = tokio::task::spawn_blocking(move || {
+        match std::fs::metadata(&path) {
+            Ok(metadata) if usize::try_from(metadata.len()).ok() == Some(size) => {},
+            _ => return false
+        }
+
+        // Epic's file hashes are the SHA1 of the whole file
+        match std::fs::File::open(&path).and_then(|mut reader| hash::sha1_reader(&mut reader)) {
+            Ok(sha) => !expected.is_empty() && hash::matches_hex(&sha, &expected),
+            Err(_) => false
+        }
+    }).await?;
+
+    Ok(installed)
+}
+
+fn split_base_urls(custom_fields: &HashMap<String, String>) -> Vec<String> {
+    match custom_fields.get("BaseUrls") {
+        Some(urls) => urls.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|url| !url.is_empty())
+            .map(str::to_owned)
+            .collect(),
+        None => Vec::new()
+    }
+}
+
+// Newer feature levels can change the layout in ways that would be silently misread
+fn check_feature_level(feature_level: i32) -> Result<()> {
+    if !(0..=EFEATURE_LEVEL_LATEST).contains(&feature_level) {
+        return Err(Box::new(ParserError::new(&format!("unsupported manifest feature level {}, the latest supported is {}",
+            feature_level, EFEATURE_LEVEL_LATEST))));
+    }
+
+    Ok(())
+}
+
+// BaseUrls point to the cloud directory, the chunks live in a subdirectory
+// that depends on the feature level of the manifest, file data in `Files`.
+// The first base uri and the mirrors to fall back to.
+fn resolve_chunk_base_uris(options: &ManifestOptions, custom_fields: &HashMap<String, String>, feature_level: i32, is_file_data: bool) -> Result<(String, Vec<String>)> {
+    if let Some((first, mirrors)) = options.chunk_base_uris.split_first() {
+        return Ok((first.clone(), mirrors.to_vec()));
+    }
+
+    let mut base_urls = split_base_urls(custom_fields).into_iter();
+    let base_url = match base_urls.next() {
+        Some(base_url) => base_url,
+        None => return Err(Box::new(ParserError::new("no chunk base uri was supplied and the manifest has no BaseUrls")))
+    };
+
+    let chunks_dir = match feature_level {
+        _ if is_file_data => "Files",
+        level if level >= EFEATURE_LEVEL_VARIABLE_SIZE_CHUNKS_WITHOUT_WINDOW_SIZE_CHUNK_INFO => "ChunksV4",
+        level if level >= EFEATURE_LEVEL_CHUNK_COMPRESSION_SUPPORT => "ChunksV3",
+        level if level >= EFEATURE_LEVEL_DATA_FILE_RENAMES => "ChunksV2",
+        _ => "Chunks"
+    };
+
+    let chunks_uri = |base_url: &str| format!("{}/{}/", base_url.trim_end_matches('/'), chunks_dir);
+    Ok((chunks_uri(&base_url), base_urls.map(|base_url| chunks_uri(&base_url)).collect()))
+}
+
+// A chunk of the chunk data list, kept as read until the chunk base uri is known
+struct ChunkRow {
+    guid: FGuid,
+    hash: u64,
+    sha: [u8; 20],
+    data_group: u8,
+    size: u64
+}
+
+impl ChunkRow {
+    fn new(guid: FGuid) -> Self {
+        Self {
+            guid,
+            hash: 0,
+            sha: [0; 20],
+            data_group: 0,
+            size: 0
+        }
+    }
+}
+
+struct ChunkTables {
+    chunk_hashes: HashMap<FGuid, String>,
+    chunk_shas: HashMap<FGuid, String>,
+    data_groups: HashMap<FGuid, u8>,
+    chunk_filesizes: HashMap<FGuid, u64>,
+    chunks: HashMap<FGuid, FileChunk>
+}
+
+impl ChunkTables {
+    // Fills the public tables and the chunks in a single pass, without intermediate maps
+    fn new(rows: Vec<ChunkRow>, chunk_base_uri: &str) -> Self {
+        let mut tables = Self {
+            chunk_hashes: HashMap::with_capacity(rows.len()),
+            chunk_shas: HashMap::with_capacity(rows.len()),
+            data_groups: HashMap::with_capacity(rows.len()),
+            chunk_filesizes: HashMap::with_capacity(rows.len()),
+            chunks: HashMap::with_capacity(rows.len())
+        };
+
+        for row in rows {
+            let hash = format!("{:016X}", row.hash);
+            let sha = hex::encode_upper(row.sha);
+            let chunk = FileChunk::new(row.guid, row.size, &hash, &sha, row.data_group, chunk_base_uri);
+            tables.chunks.insert(row.guid, chunk);
+            tables.chunk_hashes.insert(row.guid, hash);
+            tables.chunk_shas.insert(row.guid, sha);
+            tables.data_groups.insert(row.guid, row.data_group);
+            tables.chunk_filesizes.insert(row.guid, row.size);
+        }
+
+        tables
+    }
+}
+
+// In file data manifests every file is stored whole, its single part points
+// at the GUID of that data and the file SHA1 is part of its name.
+fn file_data_table(builders: &[FileManifestBuilder], chunk_base_uri: &str) -> Result<HashMap<FGuid, FileChunk>> {
+    let mut chunks: HashMap<FGuid, FileChunk> = HashMap::with_capacity(builders.len());
+    for builder in builders {
+        let part = match builder.chunk_parts.as_deref() {
+            Some([part]) if part.offset == 0 => part,
+            Some([]) | None => continue,
+            _ => return Err(Box::new(ParserError::new(&format!("file {} of a file data manifest must be a single part", builder.name))))
+        };
+
+        let sha = builder.hash.as_deref().unwrap_or_default();
+        let chunk = FileChunk::file_data(part.guid, u64::try_from(part.size)?, sha, chunk_base_uri);
+        chunks.insert(part.guid, chunk);
+    }
+
+    Ok(chunks)
+}
+
+// HashMaps serialize in arbitrary order, sort them so the JSON output can be diffed
+fn serialize_sorted<S, K, V>(map: &HashMap<K, V>, serializer: S) -> std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    K: Serialize + Ord,
+    V: Serialize
+{
+    serializer.collect_map(map.iter().collect::<BTreeMap<_, _>>())
+}
+
+// FStrings are length prefixed and null terminated, a negative length
+// marks a UTF-16 string of `-length` code units.
+fn read_fstring<R: Read>(reader: &mut R) -> Result<String> {
+    let length = reader.read_i32::<LittleEndian>()?;
+    if length == 0 {
+        return Ok(String::new());
+    }
+
+    if length > 0 {
+        let mut data = read_bytes(reader, usize::try_from(length)?)?;
+        if data.last() == Some(&0) {
+            data.pop();
+        }
+
+        return Ok(String::from_utf8(data)?);
+    }
+
+    let length = match length.checked_neg() {
+        Some(length) => usize::try_from(length)?,
+        None => return Err(Box::new(ParserError::new("corrupted archive: invalid FString length")))
+    };
+
+    let size = length.checked_mul(2).ok_or_else(|| ParserError::new("corrupted archive: invalid FString length"))?;
+    let data = read_bytes(reader, size)?;
+
+    let mut units: Vec<u16> = data.chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .collect();
+    if units.last() == Some(&0) {
+        units.pop();
+    }
+
+    Ok(String::from_utf16(&units)?)
+}
+
+// Sections are prefixed with their size, the next one starts right after it
+fn seek_past_section<R: Seek>(reader: &mut R, start_pos: u64, data_size: i32) -> Result<()> {
+    let end = match start_pos.checked_add(u64::try_from(data_size)?) {
+        Some(end) => end,
+        None => return Err(Box::new(ParserError::new("manifest section size overflows")))
+    };
+
+    reader.seek(SeekFrom::Start(end))?;
+    Ok(())
+}
+
+// Reads as much as is actually there instead of allocating `length` up front
+fn read_bytes<R: Read>(reader: &mut R, length: usize) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(length.min(MAX_PREALLOCATED_ELEMENTS));
+    reader.take(u64::try_from(length)?).read_to_end(&mut data)?;
+    if data.len() != length {
+        return Err(Box::new(ParserError::new("unexpected end of manifest data")));
+    }
+
+    Ok(data)
+}
+
+fn read_tarray<R, T, F>(reader: &mut R, f: F) -> Result<Vec<T>>
+where
+    R: Read,
+    F: FnMut(&mut R) -> Result<T>
+{
+    let length = reader.read_i32::<LittleEndian>()?;
+    read_sized_tarray(reader, length, f)
+}
+
+fn read_sized_tarray<R, T, F>(reader: &mut R, length: i32, mut f: F) -> Result<Vec<T>>
+where
+    R: Read,
+    F: FnMut(&mut R) -> Result<T>
+{
+    let mut result = Vec::with_capacity(usize::try_from(length)?.min(MAX_PREALLOCATED_ELEMENTS));
+    for _ in 0..length {
+        result.push(f(reader)?);
+    }
+
+    Ok(result)
+}
+
+// Checks the binary header and returns the decrypted and decompressed payload behind it
+fn decode_manifest_data(data: &[u8], options: &ManifestOptions) -> Result<Vec<u8>> {
+    if data.len() < MANIFEST_HEADER_MIN_SIZE {
+        return Err(Box::new(ParserError::new("unexpected end of manifest header")));
+    }
+
+    let mut cursor = Cursor::new(data);
+    let magic = cursor.read_u32::<LittleEndian>()?;
+    if magic != MANIFEST_HEADER_MAGIC {
+        return Err(Box::new(ParserError::new("unknown manifest format")));
+    }
+
+    let header_size = cursor.read_i32::<LittleEndian>()?;
+    let data_size_uncompressed = cursor.read_i32::<LittleEndian>()?;
+    let data_size_compressed = cursor.read_i32::<LittleEndian>()?;
+    let mut sha_hash = [0u8; 20];
+    cursor.read_exact(&mut sha_hash)?;
+
+    let storage_flags = cursor.read_u8()?;
+    let _version = cursor.read_i32::<LittleEndian>()?;
+    cursor.seek(SeekFrom::Start(u64::try_from(header_size)?))?;
+
+    let pos = usize::try_from(cursor.position())?;
+    let end = pos.checked_add(usize::try_from(data_size_compressed)?);
+    let mut data = match end.and_then(|end| cursor.get_ref().get(pos..end)) {
+        Some(data) => data.to_vec(),
+        None => return Err(Box::new(ParserError::new("unexpected end of manifest data")))
+    };
+    if storage_flags & EMANIFEST_STORAGE_FLAG_ENCRYPTED != 0 {
+        let key = match &options.decryption_key {
+            Some(key) => key,
+            None => return Err(Box::new(ParserError::new("the manifest is encrypted but no decryption key was supplied.")))
+        };
+
+        decrypt_aes256_ecb(&mut data, key)?;
+    }
+
+    let data_size_uncompressed = usize::try_from(data_size_uncompressed)?;
+    if storage_flags & EMANIFEST_STORAGE_FLAG_COMPRESSED != 0 {
+        data = decompress_to_vec_zlib_with_limit(&data, data_size_uncompressed)
+            .map_err(|status| ParserError::new(&format!("zlib decompression of the manifest failed: {:?}, expected {} bytes",
+                status, data_size_uncompressed)))?;
+    } else {
+        // Encrypted payloads are padded to the AES block size
+        data.truncate(data_size_uncompressed);
+    }
+
+    if data.len() != data_size_uncompressed {
+        return Err(Box::new(ParserError::new(&format!("manifest data is {} bytes, expected {}", data.len(), data_size_uncompressed))));
+    }
+
+    if options.verify_manifest {
+        let sha = hash::sha1(&data);
+        if sha != sha_hash {
+            return Err(Box::new(ParserError::new(&format!("manifest sha mismatch: expected {}, got {}",
+                hex::encode_upper(sha_hash), hash::to_hex(&sha)))));
+        }
+    }
+
+    Ok(data)
+}
+
+// Epic encrypts the stored payload in place with AES-256 in ECB mode, before compression is undone
+fn decrypt_aes256_ecb(data: &mut [u8], key: &[u8; 32]) -> Result<()> {
+    if !data.chunks_exact(16).remainder().is_empty() {
+        return Err(Box::new(ParserError::new("the encrypted manifest data is not aligned to the AES block size.")));
+    }
+
+    let cipher = Aes256::new(GenericArray::from_slice(key));
+    for block in data.chunks_exact_mut(16) {
+        cipher.decrypt_block(GenericArray::from_mut_slice(block));
+    }
+
+    Ok(())
+}
+
+fn json_str<'a>(json: &'a Value, key: &str) -> Result<&'a str> {
+    match json.get(key) {
+        Some(value) => json_value_str(value, key),
+        None => Err(Box::new(ParserError::new(&format!("missing field: {}", key))))
+    }
+}
+
+fn json_value_str<'a>(value: &'a Value, key: &str) -> Result<&'a str> {
+    value.as_str().ok_or_else(|| Box::new(ParserError::new(&format!("expected a string for field: {}", key))).into())
+}
+
+fn json_object<'a>(json: &'a Value, key: &str) -> Result<&'a serde_json::Map<String, Value>> {
+    json.get(key).and_then(Value::as_object)
+        .ok_or_else(|| Box::new(ParserError::new(&format!("missing field: {}", key))).into())
+}
+
+fn json_array<'a>(json: &'a Value, key: &str) -> Result<&'a Vec<Value>> {
+    json.get(key).and_then(Value::as_array)
+        .ok_or_else(|| Box::new(ParserError::new(&format!("missing field: {}", key))).into())
+}
+
+// JSON manifests store binary values as "blobs": every byte is written as a
+// zero padded, three digit decimal number, in little endian order.
+fn blob_to_bytes(blob: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(blob.len() / 3);
+    for digits in blob.as_bytes().chunks(3) {
+        if digits.len() != 3 {
+            return Err(Box::new(ParserError::new(&format!("invalid blob: {}", blob))));
+        }
+
+        bytes.push(std::str::from_utf8(digits)?.parse::<u8>()?);
+    }
+
+    Ok(bytes)
+}
+
+fn blob_to_u64(blob: &str) -> Result<u64> {
+    let bytes = blob_to_bytes(blob)?;
+    if bytes.len() > 8 {
+        return Err(Box::new(ParserError::new(&format!("blob is too large for an integer: {}", blob))));
+    }
+
+    Ok(bytes.iter().rev().fold(0u64, |value, byte| (value << 8) | u64::from(*byte)))
+}
+
+fn blob_to_u32(blob: &str) -> Result<u32> {
+    Ok(u32::try_from(blob_to_u64(blob)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use aes::cipher::BlockEncrypt;
+
+    use super::*;
+
+    // FIPS-197 appendix C.3
+    const KEY: [u8; 32] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+        0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f
+    ];
+    const PLAINTEXT: &str = "00112233445566778899aabbccddeeff";
+    const CIPHERTEXT: &str = "8ea2b7ca516745bfeafc49904b496089";
+
+    // the fields every JSON manifest needs, without any files or chunks
+    fn json_manifest() -> Value {
+        serde_json::json!({
+            "ManifestFileVersion": "013000000000",
+            "AppID": "000000000000",
+            "AppNameString": "Test",
+            "BuildVersionString": "1.0.0",
+            "LaunchExeString": "Test.exe",
+            "LaunchCommand": "",
+            "FileManifestList": [],
+            "ChunkHashList": {},
+            "ChunkShaList": {},
+            "DataGroupList": {},
+            "ChunkFilesizeList": {}
+        })
+    }
+
+    #[test]
+    fn decrypt_aes256_ecb_known_block() {
+        let mut data = hex::decode(CIPHERTEXT).unwrap();
+        decrypt_aes256_ecb(&mut data, &KEY).unwrap();
+        assert_eq!(hex::encode(data), PLAINTEXT);
+    }
+
+    #[test]
+    fn decrypt_aes256_ecb_round_trip() {
+        let plaintext: Vec<u8> = (0..64).collect();
+        let mut data = plaintext.clone();
+        let cipher = Aes256::new(GenericArray::from_slice(&KEY));
+        for block in data.chunks_exact_mut(16) {
+            cipher.encrypt_block(GenericArray::from_mut_slice(block));
+        }
+
+        assert_ne!(data, plaintext);
+        decrypt_aes256_ecb(&mut data, &KEY).unwrap();
+        assert_eq!(data, plaintext);
+    }
+
+    #[test]
+    fn decrypt_aes256_ecb_rejects_partial_block() {
+        let mut data = vec![0; 20];
+        assert!(decrypt_aes256_ecb(&mut data, &KEY).is_err());
+    }
+
+    #[test]
+    fn guid_round_trip() {
+        let guid: FGuid = "0123456789ABCDEFFEDCBA9876543210".parse().unwrap();
+        assert_eq!(guid, FGuid { a: 0x0123_4567, b: 0x89AB_CDEF, c: 0xFEDC_BA98, d: 0x7654_3210 });
+        assert_eq!(guid.to_string(), "0123456789ABCDEFFEDCBA9876543210");
+
+        let lowercase: FGuid = "0123456789abcdeffedcba9876543210".parse().unwrap();
+        assert_eq!(lowercase, guid);
+
+        // the binary format stores each group little endian
+        let bytes = hex::decode("67452301EFCDAB8998BADCFE10325476").unwrap();
+        assert_eq!(FGuid::new(&mut Cursor::new(bytes)).unwrap(), guid);
+    }
+
+    #[test]
+    fn guid_rejects_invalid_strings() {
+        for invalid in [
+            "",
+            "0123456789ABCDEFFEDCBA987654321",
+            "0123456789ABCDEFFEDCBA98765432100",
+            "0123456789ABCDEFFEDCBA987654321G",
+            "+123456789ABCDEFFEDCBA9876543210",
+            "01234567-89AB-CDEF-FEDC-BA9876543210",
+            "0123456789ABCDEFFEDCBA98765432é"
+        ] {
+            assert!(invalid.parse::<FGuid>().is_err(), "{} was accepted", invalid);
+        }
+    }
+
+    #[test]
+    fn chunk_tables_name_chunks_like_the_cdn() {
+        let guid: FGuid = "33333333000022220000333300000003".parse().unwrap();
+        let mut row = ChunkRow::new(guid);
+        row.hash = 0xDEAD_BEEF02;
+        row.data_group = 5;
+        let tables = ChunkTables::new(vec![row], "https://download.epicgames.com/Builds/Fortnite/CloudDir/ChunksV4/");
+
+        let chunk = &tables.chunks[&guid];
+        assert_eq!(tables.chunk_hashes[&guid], "000000DEADBEEF02");
+        assert_eq!(chunk.file_name, "000000DEADBEEF02_33333333000022220000333300000003.chunk");
+        assert_eq!(chunk.uri, "https://download.epicgames.com/Builds/Fortnite/CloudDir/ChunksV4/05/000000DEADBEEF02_33333333000022220000333300000003.chunk");
+    }
+
+    #[tokio::test]
+    async fn installed_file_is_checked_against_its_hash() {
+        let dir = std::env::temp_dir().join(format!("epic_manifest_parser_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("abc.txt");
+
+        let context = Arc::new(ManifestContext::new(Arc::new(HashMap::new()), Arc::new(HttpService::new()), &ManifestOptions::new(None, None)));
+        let part = || FileChunkPart { guid: "33333333000022220000333300000003".parse().unwrap(), offset: 0, size: 3 };
+        let mut builder = FileManifestBuilder::new("abc.txt");
+        // SHA1 of "abc"
+        builder.set_hash("A9993E364706816ABA3E25717850C26C9CD0D89D").set_chunk_parts(vec![part()]);
+        let file = builder.build(context).unwrap();
+
+        std::fs::write(&path, b"abc").unwrap();
+        assert!(is_installed(&file, &path).await.unwrap());
+
+        let mut lowercase = FileManifestBuilder::new("abc.txt");
+        lowercase.set_hash("a9993e364706816aba3e25717850c26c9cd0d89d").set_chunk_parts(vec![part()]);
+        assert!(is_installed(&lowercase.build(file.context.clone()).unwrap(), &path).await.unwrap());
+
+        std::fs::write(&path, b"abd").unwrap();
+        assert!(!is_installed(&file, &path).await.unwrap());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn json_manifest_without_chunk_filesizes() {
+        let guid: FGuid = "33333333000022220000333300000003".parse().unwrap();
+        let mut json = json_manifest();
+        // 0x000000DEADBEEF02 as little endian blob
+        json["ChunkHashList"] = serde_json::json!({ guid.to_string(): "002239190173222000000000" });
+        json["ChunkShaList"] = serde_json::json!({ guid.to_string(): "a9993e364706816aba3e25717850c26c9cd0d89d" });
+        json["DataGroupList"] = serde_json::json!({ guid.to_string(): "005" });
+        json.as_object_mut().unwrap().remove("ChunkFilesizeList");
+        let options = || ManifestOptions::new(Some("http://localhost/ChunksV4/"), None);
+
+        let manifest = Manifest::new(json.to_string().into_bytes(), options()).unwrap();
+        assert_eq!(manifest.chunk_hashes[&guid], "000000DEADBEEF02");
+        assert_eq!(manifest.chunk_shas[&guid], "A9993E364706816ABA3E25717850C26C9CD0D89D");
+        assert_eq!(manifest.data_groups[&guid], 5);
+        assert_eq!(manifest.chunk_filesizes[&guid], 0);
+
+        json["ChunkFilesizeList"] = serde_json::json!({ guid.to_string(): "001004000000000000000000" });
+        let manifest = Manifest::new(json.to_string().into_bytes(), options()).unwrap();
+        assert_eq!(manifest.chunk_filesizes[&guid], 1025);
+
+        json["ChunkShaList"] = serde_json::json!({ guid.to_string(): "a9993e36" });
+        assert!(Manifest::new(json.to_string().into_bytes(), options()).is_err());
+    }
+
+    #[tokio::test]
+    async fn download_files_stays_in_the_output_dir() {
+        let dir = std::env::temp_dir().join(format!("epic_manifest_parser_traversal_{}", std::process::id()));
+        let output_dir = dir.join("out");
+        std::fs::create_dir_all(&output_dir).unwrap();
+
+        let empty_file = |name: String| serde_json::json!({ "Filename": name, "FileHash": "000".repeat(20), "FileChunkParts": [] });
+        let mut json = json_manifest();
+        json["FileManifestList"] = serde_json::json!([
+            empty_file("../escape.txt".to_owned()),
+            empty_file(dir.join("absolute.txt").display().to_string())
+        ]);
+        let manifest = Manifest::new(json.to_string().into_bytes(), ManifestOptions::new(Some("http://localhost/ChunksV4/"), None)).unwrap();
+
+        for file in &manifest.file_manifests {
+            assert!(manifest.download_files(&[file], &output_dir, 1).await.is_err(), "{} was downloaded", file.name);
+        }
+        assert!(!dir.join("escape.txt").exists());
+        assert!(!dir.join("absolute.txt").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
\ No newline at end of file
@@ -0,0 +1,77 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use bytes::Bytes;
+
+use crate::manifest::FGuid;
+
+// Least recently used decompressed chunks, bounded by their combined size so
+// parts of the same chunk that are loaded one after another share a download.
+#[derive(Debug)]
+pub struct ChunkMemoryCache {
+    max_size: usize,
+    entries: Mutex<Entries>
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    chunks: HashMap<FGuid, Bytes>,
+    // least recently used first
+    order: VecDeque<FGuid>,
+    size: usize
+}
+
+impl ChunkMemoryCache {
+
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            max_size,
+            entries: Mutex::new(Entries::default())
+        }
+    }
+
+    pub fn get(&self, guid: &FGuid) -> Option<Bytes> {
+        let mut entries = self.entries.lock().unwrap();
+        let data = entries.chunks.get(guid)?.clone();
+        entries.touch(guid);
+
+        Some(data)
+    }
+
+    /// Chunks larger than the whole cache aren't kept.
+    pub fn insert(&self, guid: FGuid, data: Bytes) {
+        if data.len() > self.max_size {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(old) = entries.chunks.insert(guid, data.clone()) {
+            entries.size -= old.len();
+            entries.touch(&guid);
+        } else {
+            entries.order.push_back(guid);
+        }
+        entries.size += data.len();
+
+        while entries.size > self.max_size {
+            let oldest = match entries.order.pop_front() {
+                Some(oldest) => oldest,
+                None => break
+            };
+
+            if let Some(evicted) = entries.chunks.remove(&oldest) {
+                entries.size -= evicted.len();
+            }
+        }
+    }
+
+}
+
+impl Entries {
+    fn touch(&mut self, guid: &FGuid) {
+        if let Some(index) = self.order.iter().position(|entry| entry == guid) {
+            self.order.remove(index);
+        }
+        self.order.push_back(*guid);
+    }
+}
\ No newline at end of file
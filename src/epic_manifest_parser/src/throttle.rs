@@ -0,0 +1,73 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Token bucket shared by every download of a manifest, holding at most one
+// second worth of bytes so idle periods can't build up a large burst.
+// Callers reserve their bytes up front and sleep without holding the lock, the
+// bucket goes into debt so later callers wait for the bytes reserved before them.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_second: f64,
+    bucket: Mutex<Bucket>
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant
+}
+
+impl RateLimiter {
+
+    pub fn new(bytes_per_second: u64) -> Self {
+        let bytes_per_second = bytes_per_second.max(1) as f64;
+        Self {
+            bytes_per_second,
+            bucket: Mutex::new(Bucket {
+                tokens: bytes_per_second,
+                last_refill: Instant::now()
+            })
+        }
+    }
+
+    /// Takes `bytes` from the budget and waits until they are paid for.
+    pub async fn acquire(&self, bytes: usize) {
+        tokio::time::sleep(self.reserve(bytes as f64)).await;
+    }
+
+    /// Waits until the bytes already taken by others are paid for, without taking any.
+    pub async fn ready(&self) {
+        tokio::time::sleep(self.reserve(0.0)).await;
+    }
+
+    fn reserve(&self, bytes: f64) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap();
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.bytes_per_second).min(self.bytes_per_second);
+        bucket.last_refill = now;
+
+        bucket.tokens -= bytes;
+        Duration::from_secs_f64((-bucket.tokens).max(0.0) / self.bytes_per_second)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserve_goes_into_debt() {
+        let rate_limiter = RateLimiter::new(1000);
+        assert!(rate_limiter.reserve(1000.0) < Duration::from_millis(10));
+
+        // the bytes of earlier callers are paid for before the later ones
+        let first = rate_limiter.reserve(500.0);
+        let second = rate_limiter.reserve(500.0);
+        assert!(first > Duration::from_millis(400) && first <= Duration::from_millis(500));
+        assert!(second > Duration::from_millis(900) && second <= Duration::from_secs(1));
+        assert!(rate_limiter.reserve(0.0) > Duration::from_millis(900));
+    }
+}
\ No newline at end of file